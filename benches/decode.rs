@@ -0,0 +1,30 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use toki_pona_encoding::encoding::{encode, Decoder};
+
+/// About a megabyte of encoded text, mixing words, punctuation and attached words.
+fn large_blob() -> Vec<u8> {
+    let passage = "mi wile toki e ni: toki pona li pona tawa mi. sina sona ala sona e tokipona? \
+        jan pi ma mute li kama sona e toki ni, li kepeken e ona lon tenpo ali!";
+    let mut blob = Vec::new();
+    while blob.len() < 1 << 20 {
+        blob.extend(encode(passage).unwrap());
+    }
+    blob
+}
+
+fn decode_large_stream(c: &mut Criterion) {
+    let blob = large_blob();
+    let mut group = c.benchmark_group("decode");
+    group.throughput(Throughput::Bytes(blob.len() as u64));
+    group.bench_function("read_bytes", |b| {
+        b.iter(|| {
+            let mut decoded = String::new();
+            Decoder::new(&mut decoded).read_bytes(&blob).unwrap();
+            decoded
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode_large_stream);
+criterion_main!(benches);
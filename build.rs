@@ -0,0 +1,94 @@
+//! Discovers every dictionary in `src/dicts` and generates a static for each one,
+//! along with the list of dictionaries in the default dictionary set.
+
+use std::{env, fmt::Write, fs, path::Path};
+
+fn main() {
+    let dicts_dir = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("src/dicts");
+    println!("cargo:rerun-if-changed={}", dicts_dir.display());
+
+    // The dictionaries in the default set, each with the feature that enables it.
+    let manifest = fs::read_to_string(dicts_dir.join("default.txt")).unwrap();
+    let defaults = manifest
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let mut parts = line.split_whitespace();
+            (parts.next().unwrap(), parts.next())
+        })
+        .collect::<Vec<_>>();
+    let feature_of = |name: &str| {
+        defaults
+            .iter()
+            .find(|(default, _)| *default == name)
+            .and_then(|(_, feature)| *feature)
+    };
+
+    // Each dictionary's name and extension, which is either `csv` or tab-separated `tsv`.
+    let mut names = fs::read_dir(&dicts_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter_map(|path| {
+            let ext = path.extension()?.to_str()?;
+            let delimiter = match ext {
+                "csv" => ',',
+                "tsv" => '\t',
+                _ => return None,
+            };
+            let name = path.file_stem().unwrap().to_str().unwrap().to_owned();
+            Some((name, ext.to_owned(), delimiter))
+        })
+        .collect::<Vec<_>>();
+    names.sort();
+    for (name, _) in &defaults {
+        assert!(
+            names.iter().any(|(n, _, _)| n == name),
+            "default dictionary {} has no CSV or TSV file",
+            name
+        );
+    }
+
+    let mut code = String::new();
+    for (name, ext, delimiter) in &names {
+        if let Some(feature) = feature_of(name) {
+            writeln!(code, "#[cfg(feature = {:?})]", feature).unwrap();
+        }
+        writeln!(
+            code,
+            "lazy_static::lazy_static! {{\n    \
+            /// The words listed in `dicts/{name}.{ext}`.\n    \
+            pub static ref {}: Dictionary<'static> = \
+            Dictionary::from_delimited(include_str!({:?}), {:?});\n}}",
+            name.to_uppercase(),
+            dicts_dir
+                .join(format!("{}.{}", name, ext))
+                .display()
+                .to_string(),
+            delimiter,
+            name = name,
+            ext = ext,
+        )
+        .unwrap();
+    }
+
+    code.push_str(
+        "/// The dictionaries in the default dictionary set, in order.\n\
+        #[allow(unused_mut, clippy::vec_init_then_push)]\n\
+        pub fn default_dictionaries() -> Vec<&'static Dictionary<'static>> {\n    \
+        let mut dictionaries = Vec::new();\n",
+    );
+    for (name, feature) in &defaults {
+        if let Some(feature) = feature {
+            writeln!(code, "    #[cfg(feature = {:?})]", feature).unwrap();
+        }
+        writeln!(code, "    dictionaries.push(&*{});", name.to_uppercase()).unwrap();
+    }
+    code.push_str("    dictionaries\n}\n");
+
+    fs::write(
+        Path::new(&env::var("OUT_DIR").unwrap()).join("dicts.rs"),
+        code,
+    )
+    .unwrap();
+}
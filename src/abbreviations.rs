@@ -0,0 +1,28 @@
+use crate::dict_set::{DictionarySet, WordIdentifier};
+
+/// Shorthand used in toki pona chat, such as `tp` for `toki pona`, which the encoder's
+/// `abbreviations` option encodes as a two-byte Abbreviation instruction. It decodes to
+/// the words of its expansion, or back to the shorthand if the decoder is asked to contract
+/// it. An abbreviation's code is its index in this list, so new abbreviations must be
+/// added at the end.
+pub const ABBREVIATIONS: &[(&str, &str)] =
+    &[("tp", "toki pona"), ("jp", "jan pona"), ("kp", "kama pona")];
+
+/// Returns the code of the given shorthand, if it is an abbreviation.
+pub fn code(shorthand: &str) -> Option<u8> {
+    let index = ABBREVIATIONS
+        .iter()
+        .position(|(abbreviation, _)| *abbreviation == shorthand)?;
+    u8::try_from(index).ok()
+}
+
+/// Returns the words of the expansion of the abbreviation with the given code, looked up in
+/// the dictionary set. Returns None if there is no such abbreviation, or a word of it is
+/// not in the dictionary set.
+pub fn words(dict_set: &DictionarySet, code: u8) -> Option<Vec<WordIdentifier>> {
+    let (_, expansion) = ABBREVIATIONS.get(code as usize)?;
+    expansion
+        .split(' ')
+        .map(|word| dict_set.get_identifier(word))
+        .collect()
+}
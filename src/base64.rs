@@ -0,0 +1,57 @@
+//! A minimal base64 implementation, so that encoded streams can be sent through text.
+
+/// The standard base64 alphabet, as used in MIME and JSON.
+pub(crate) const STANDARD: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+/// The URL and filename safe base64 alphabet.
+pub(crate) const URL_SAFE: &[u8; 64] =
+    b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+/// Converts bytes to base64 using the given alphabet, with `=` padding if requested.
+pub(crate) fn encode(bytes: &[u8], alphabet: &[u8; 64], pad: bool) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let group = chunk.iter().enumerate().fold(0u32, |group, (i, byte)| {
+            group | (*byte as u32) << (16 - 8 * i)
+        });
+        // A chunk of n bytes needs n + 1 characters.
+        for i in 0..=chunk.len() {
+            text.push(alphabet[(group >> (18 - 6 * i) & 0x3f) as usize] as char);
+        }
+        if pad {
+            for _ in chunk.len()..3 {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+/// Converts base64 in the given alphabet back to bytes. Padding is optional.
+/// If the text is not valid base64, returns the byte offset of the first invalid character.
+pub(crate) fn decode(text: &str, alphabet: &[u8; 64]) -> Result<Vec<u8>, usize> {
+    let data = text.trim_end_matches('=');
+    if text.len() - data.len() > 2 {
+        return Err(data.len() + 2);
+    }
+    // A single character left over does not make up a whole byte.
+    if data.len() % 4 == 1 {
+        return Err(data.len() - 1);
+    }
+
+    let mut bytes = Vec::with_capacity(data.len() / 4 * 3 + 2);
+    for (chunk_idx, chunk) in data.as_bytes().chunks(4).enumerate() {
+        let mut group = 0u32;
+        for (i, c) in chunk.iter().enumerate() {
+            let value = alphabet
+                .iter()
+                .position(|a| a == c)
+                .ok_or(chunk_idx * 4 + i)?;
+            group |= (value as u32) << (18 - 6 * i);
+        }
+        for i in 0..chunk.len() - 1 {
+            bytes.push((group >> (16 - 8 * i)) as u8);
+        }
+    }
+    Ok(bytes)
+}
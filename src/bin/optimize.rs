@@ -0,0 +1,76 @@
+//! Reorders a dictionary so that the most frequent words in a corpus come first,
+//! and are therefore assigned the cheapest bytes.
+//!
+//! Usage: `cargo run --bin optimize -- <dictionary.csv> < corpus.txt > reordered.csv`
+//!
+//! Word frequencies are measured by encoding the corpus line by line with the default
+//! dictionary set; lines that cannot be encoded are reported and skipped.
+//! The words of phrases, abbreviations and cartouches are counted as if they were
+//! written out one by one.
+//! A dictionary ending in `.tsv` is read as tab-separated, as in `build.rs`.
+//! Note that reordering a dictionary changes the bytes assigned to its words,
+//! so text encoded with the old ordering will no longer decode correctly.
+
+use std::{collections::HashMap, io::Read};
+
+use toki_pona_encoding::{
+    dict::Dictionary,
+    dict_set::DICT_SET,
+    encoding::{encode, instructions, Instruction},
+};
+
+fn main() {
+    let path = std::env::args()
+        .nth(1)
+        .expect("usage: optimize <dictionary.csv> < corpus.txt");
+    let dictionary = std::fs::read_to_string(&path).expect("could not read dictionary");
+    let delimiter = if path.ends_with(".tsv") { '\t' } else { ',' };
+    let parsed = Dictionary::try_from_delimited(&dictionary, delimiter)
+        .unwrap_or_else(|err| panic!("invalid dictionary: {}", err));
+    let mut corpus = String::new();
+    std::io::stdin()
+        .read_to_string(&mut corpus)
+        .expect("could not read corpus");
+
+    // Count how many times each word appears, keyed by its default spelling.
+    let mut frequencies = HashMap::<&str, usize>::new();
+    let mut encoded_len = 0;
+    for (line_number, line) in corpus.lines().enumerate() {
+        let encoded = match encode(line) {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                eprintln!("skipping line {}: {}", line_number + 1, err);
+                continue;
+            }
+        };
+        encoded_len += encoded.len();
+        for instruction in instructions(&encoded) {
+            let words = match instruction.expect("encoder produced invalid bytes") {
+                Instruction::Cartouche { words } => words,
+                instruction => instruction.expanded_words(&DICT_SET).unwrap(),
+            };
+            for word in words {
+                *frequencies.entry(DICT_SET.word_str(word)).or_default() += 1;
+            }
+        }
+    }
+    eprintln!(
+        "encoded {} bytes of corpus into {} bytes",
+        corpus.len(),
+        encoded_len
+    );
+
+    // Sort the records by descending frequency.
+    // The sort is stable, so words with equal frequency keep their original order.
+    // The parsed dictionary has one word for each record, in the same order.
+    let mut lines = dictionary.lines();
+    let header = lines.next().unwrap();
+    let mut records = lines.zip(&parsed.default.words).collect::<Vec<_>>();
+    records
+        .sort_by_key(|(_, word)| std::cmp::Reverse(frequencies.get(*word).copied().unwrap_or(0)));
+
+    println!("{}", header);
+    for (record, _) in records {
+        println!("{}", record);
+    }
+}
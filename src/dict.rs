@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::variation::Variation;
+use crate::{error::DictionaryError, variation::Variation};
 
 /// Stores a list of words in toki pona, including variants for
 /// other orthographic systems such as tipunsin and CJK characters.
@@ -10,9 +10,10 @@ pub struct Dictionary<'a> {
     pub variations: HashMap<Variation, VariationDictionary<'a>>,
 }
 
-lazy_static::lazy_static! {
-    pub static ref PU: Dictionary<'static> = Dictionary::from_csv(include_str!("dicts/pu.csv"));
-}
+// Every `dicts/*.csv` file, or tab-separated `dicts/*.tsv` file, is a static named after
+// the file, such as `PU` for `pu.csv`.
+// The default dictionary set is listed in `dicts/default.txt`; see `build.rs`.
+include!(concat!(env!("OUT_DIR"), "/dicts.rs"));
 
 /// Represents the dictionary of words for the default orthographic system
 /// for toki pona. This is the version toki pona that is most commonly seen
@@ -39,45 +40,184 @@ impl<'a> Dictionary<'a> {
     /// The first row states the variation, and subsequent lines are words in each
     /// variation. The default variation may not have empty entries.
     /// No quote marks or extra commas are expected.
+    /// Words must be written in lower case. Capitalisation is not part of a word; it is
+    /// recorded separately when encoding, using Capitalize or CaseMask instructions.
+    /// Panics if the CSV is malformed; see `try_from_csv`.
     pub fn from_csv(text: &'a str) -> Self {
+        Self::try_from_csv(text).unwrap_or_else(|err| panic!("invalid dictionary: {}", err))
+    }
+
+    /// Parses a CSV file like `from_csv`, but returns an error if it is malformed,
+    /// such as when a line has a different number of columns to the header, or a word is
+    /// spelt the same way twice in one column.
+    pub fn try_from_csv(text: &'a str) -> Result<Self, DictionaryError> {
+        Self::try_from_delimited(text, ',')
+    }
+
+    /// Parses a dictionary file like `from_csv`, but with columns separated by the given
+    /// delimiter instead of commas, such as `'\t'` for a tab-separated file.
+    /// Panics if the file is malformed; see `try_from_delimited`.
+    pub fn from_delimited(text: &'a str, delimiter: char) -> Self {
+        Self::try_from_delimited(text, delimiter)
+            .unwrap_or_else(|err| panic!("invalid dictionary: {}", err))
+    }
+
+    /// Parses a dictionary file like `from_delimited`, but returns an error if it is
+    /// malformed, as `try_from_csv` does.
+    pub fn try_from_delimited(text: &'a str, delimiter: char) -> Result<Self, DictionaryError> {
         let mut lines = text.lines();
         let mut headers = lines
             .next()
-            .expect("expected headers listing variations")
-            .split(',');
-        assert_eq!(headers.next(), Some("tp"), "expected default variation");
+            .ok_or(DictionaryError::MissingHeaders)?
+            .split(delimiter);
+        if headers.next() != Some("tp") {
+            return Err(DictionaryError::MissingDefaultColumn);
+        }
 
         let mut default = DefaultDictionary {
             words: Vec::new(),
             lookup: HashMap::new(),
         };
+        // Columns for variations whose feature is disabled are checked, but not stored.
         let mut variations = Vec::new();
         let mut variation_names = Vec::new();
         for variation in headers {
-            variations.push(VariationDictionary {
+            let name =
+                Variation::try_from(variation).map_err(|()| DictionaryError::UnknownVariation {
+                    name: variation.to_owned(),
+                })?;
+            variations.push(name.is_enabled().then(|| VariationDictionary {
                 words: Vec::new(),
                 lookup: HashMap::new(),
-            });
-            variation_names.push(variation.try_into().expect("not a known variation"));
+            }));
+            variation_names.push(name);
         }
 
         for (i, record) in lines.enumerate() {
-            let mut words = record.split(',');
+            let found = record.split(delimiter).count();
+            if found != variations.len() + 1 {
+                return Err(DictionaryError::WrongColumnCount {
+                    // Line numbers count from 1, and the header is on line 1.
+                    line: i + 2,
+                    expected: variations.len() + 1,
+                    found,
+                });
+            }
+            if record.chars().any(char::is_uppercase) {
+                return Err(DictionaryError::UpperCaseWord { line: i + 2 });
+            }
+            let mut words = record.split(delimiter);
             let word = words.next().unwrap();
-            default.lookup.insert(word, i);
+            // A word spelt twice would be looked up as only one of its entries.
+            if default.lookup.insert(word, i).is_some() {
+                return Err(DictionaryError::DuplicateWord {
+                    word: word.to_owned(),
+                    variation: Variation::Default,
+                });
+            }
             default.words.push(word);
 
-            for (word, variation) in words.zip(&mut variations) {
-                if !word.is_empty() {
-                    variation.lookup.insert(word, i);
+            for ((word, variation), name) in words
+                .zip(variations.iter_mut().map(Option::as_mut))
+                .zip(&variation_names)
+            {
+                let Some(variation) = variation else {
+                    continue;
+                };
+                if word.is_empty() {
+                    variation.words.push(None);
+                } else {
+                    if variation.lookup.insert(word, i).is_some() {
+                        return Err(DictionaryError::DuplicateWord {
+                            word: word.to_owned(),
+                            variation: *name,
+                        });
+                    }
                     variation.words.push(Some(word));
                 }
             }
         }
 
-        Dictionary {
+        Ok(Dictionary {
             default,
-            variations: variation_names.into_iter().zip(variations).collect(),
+            variations: variation_names
+                .into_iter()
+                .zip(variations)
+                .filter_map(|(name, variation)| Some((name, variation?)))
+                .collect(),
+        })
+    }
+
+    /// Creates a dictionary containing the words of this dictionary followed by the words
+    /// of the other, keeping every variation of either. Where a variation has no spelling
+    /// for a word because only one dictionary has that variation, the gap is left empty.
+    /// Returns an error if both dictionaries spell a word the same way in any variation.
+    pub fn merge(&self, other: &Dictionary<'a>) -> Result<Dictionary<'a>, DictionaryError> {
+        let offset = self.default.words.len();
+        let mut default = DefaultDictionary {
+            words: self.default.words.clone(),
+            lookup: self.default.lookup.clone(),
+        };
+        for (i, word) in other.default.words.iter().enumerate() {
+            if default.lookup.insert(word, offset + i).is_some() {
+                return Err(DictionaryError::DuplicateWord {
+                    word: word.to_string(),
+                    variation: Variation::Default,
+                });
+            }
+            default.words.push(word);
+        }
+
+        let mut variations = HashMap::new();
+        for variation in self.variations.keys().chain(other.variations.keys()) {
+            if variations.contains_key(variation) {
+                continue;
+            }
+            let mut merged = VariationDictionary {
+                words: Vec::new(),
+                lookup: HashMap::new(),
+            };
+            for (dict, len) in [(self, offset), (other, other.default.words.len())] {
+                let words = dict.variations.get(variation).map(|v| &v.words);
+                for i in 0..len {
+                    let word = words.and_then(|words| words[i]);
+                    if let Some(word) = word {
+                        if merged.lookup.insert(word, merged.words.len()).is_some() {
+                            return Err(DictionaryError::DuplicateWord {
+                                word: word.to_string(),
+                                variation: *variation,
+                            });
+                        }
+                    }
+                    merged.words.push(word);
+                }
+            }
+            variations.insert(*variation, merged);
         }
+
+        Ok(Dictionary {
+            default,
+            variations,
+        })
+    }
+
+    /// Returns true if every lookup table agrees with its list of words, so that looking
+    /// up a spelling finds the index of an entry with that spelling, and every spelling can
+    /// be looked up. Dictionaries built by `try_from_delimited` and `merge` always agree.
+    pub fn is_consistent(&self) -> bool {
+        let default = &self.default;
+        default.words.len() == default.lookup.len()
+            && default
+                .lookup
+                .iter()
+                .all(|(word, i)| default.words.get(*i) == Some(word))
+            && self.variations.values().all(|variation| {
+                variation.words.len() == default.words.len()
+                    && variation.words.iter().flatten().count() == variation.lookup.len()
+                    && variation
+                        .lookup
+                        .iter()
+                        .all(|(word, i)| variation.words.get(*i) == Some(&Some(*word)))
+            })
     }
 }
@@ -1,15 +1,40 @@
-use crate::{dict::*, variation::Variation};
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    dict::*,
+    encoding::{Opcode, FIRST_WORD_BYTE},
+    error::{DictionaryError, EncodeError},
+    variation::Variation,
+};
 
 /// Indexes all words and variants in toki pona.
 /// This uses multiple dictionaries to categorise each word.
+///
+/// A dictionary set is never modified after it is created, and is `Send` and `Sync`,
+/// so a single set such as `DICT_SET` can be shared by encoders on many threads.
+#[derive(Debug)]
 pub struct DictionarySet<'a> {
     /// A list of dictionaries whose words will be directly encoded as a single byte.
     base_dictionaries: Vec<&'a Dictionary<'a>>,
+    /// The index of the first word of each base dictionary among all of their words.
+    offsets: Vec<usize>,
+    /// Every word in the base dictionaries, in the order of their bytes.
+    /// This makes looking up the word for a byte a single index, rather than a search.
+    word_order: Vec<WordIdentifier>,
+    /// The number of characters in the longest spelling in each variation.
+    longest_spelling: HashMap<Variation, usize>,
+    /// A trie of the spellings in each variation, used to split text into words.
+    /// This is built once with the set, so `DICT_SET` shares it between every encoder.
+    spellings: HashMap<Variation, SpellingTrie>,
+    /// The byte that represents the first word; see `with_first_word_byte`.
+    first_word_byte: u8,
+    /// The amount added to the byte of every control opcode; see `with_opcode_base`.
+    opcode_base: u8,
 }
 
 /// Uniquely identifies a word, and possibly a variant.
 /// Linked to a single DictionarySet.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct WordIdentifier {
     /// Which dictionary is this word stored in?
     pub dict: usize,
@@ -17,11 +42,59 @@ pub struct WordIdentifier {
     pub word: usize,
 }
 
+/// Every spelling of the words in one variation, stored so that the words at the start of
+/// some text can be found by walking through its characters once.
+#[derive(Debug, Default)]
+struct SpellingTrie {
+    /// The root is the first node. Each node maps the next character of a spelling to the
+    /// index of its child, and stores the word spelt by the characters up to that node.
+    nodes: Vec<(BTreeMap<char, usize>, Option<WordIdentifier>)>,
+}
+
+impl SpellingTrie {
+    /// Adds a spelling of a word. If the spelling is already in the trie, the existing word
+    /// is kept, as lookups use the first dictionary that has a word.
+    fn insert(&mut self, spelling: &str, word: WordIdentifier) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Default::default());
+        }
+        let mut node = 0;
+        for c in spelling.chars() {
+            let len = self.nodes.len();
+            node = *self.nodes[node].0.entry(c).or_insert(len);
+            if node == len {
+                self.nodes.push(Default::default());
+            }
+        }
+        self.nodes[node].1.get_or_insert(word);
+    }
+
+    /// Iterates over each word whose spelling is a prefix of the text, shortest first,
+    /// along with the length of its spelling in bytes.
+    fn prefixes<'t>(&'t self, text: &'t str) -> impl Iterator<Item = (usize, WordIdentifier)> + 't {
+        text.char_indices()
+            .scan(0, move |node, (i, c)| {
+                *node = *self.nodes.get(*node)?.0.get(&c)?;
+                Some((i + c.len_utf8(), self.nodes[*node].1))
+            })
+            .filter_map(|(len, word)| Some((len, word?)))
+    }
+}
+
+/// What a single byte means at the start of an instruction.
+#[derive(Debug, Clone, Copy)]
+pub enum ByteMeaning {
+    /// This byte is a single-byte word.
+    Word(WordIdentifier),
+    /// This byte begins a control instruction.
+    Opcode(Opcode),
+    /// This byte is not yet assigned a meaning.
+    Free,
+}
+
 impl<'a> Default for DictionarySet<'a> {
     fn default() -> Self {
-        Self {
-            base_dictionaries: vec![&*PU],
-        }
+        Self::new(default_dictionaries())
     }
 }
 
@@ -30,12 +103,146 @@ lazy_static::lazy_static! {
 }
 
 impl<'a> DictionarySet<'a> {
+    /// Creates a dictionary set from the given dictionaries.
+    /// Words are assigned bytes in the order of these dictionaries.
+    /// In debug builds, panics if a dictionary's lookup tables do not agree with its lists
+    /// of words; see `Dictionary::is_consistent`.
+    pub fn new(base_dictionaries: Vec<&'a Dictionary<'a>>) -> Self {
+        debug_assert!(
+            base_dictionaries.iter().all(|dict| dict.is_consistent()),
+            "dictionary lookup tables do not match their words"
+        );
+        let offsets = base_dictionaries
+            .iter()
+            .scan(0, |offset, dict| {
+                let start = *offset;
+                *offset += dict.default.words.len();
+                Some(start)
+            })
+            .collect();
+        let mut longest_spelling = HashMap::new();
+        let mut spellings = HashMap::<Variation, SpellingTrie>::new();
+        let mut record = |variation, spelling: &str, word| {
+            let longest = longest_spelling.entry(variation).or_insert(0);
+            *longest = spelling.chars().count().max(*longest);
+            spellings
+                .entry(variation)
+                .or_default()
+                .insert(spelling, word);
+        };
+        for (dict_idx, dict) in base_dictionaries.iter().enumerate() {
+            for (word_idx, spelling) in dict.default.words.iter().enumerate() {
+                let word = WordIdentifier {
+                    dict: dict_idx,
+                    word: word_idx,
+                };
+                record(Variation::Default, spelling, word);
+            }
+            for (variation, variation_dict) in &dict.variations {
+                for (word_idx, spelling) in variation_dict.words.iter().enumerate() {
+                    let word = WordIdentifier {
+                        dict: dict_idx,
+                        word: word_idx,
+                    };
+                    if let Some(spelling) = spelling {
+                        record(*variation, spelling, word);
+                    }
+                }
+            }
+        }
+        let mut dict_set = Self {
+            base_dictionaries,
+            offsets,
+            word_order: Vec::new(),
+            longest_spelling,
+            spellings,
+            first_word_byte: FIRST_WORD_BYTE,
+            opcode_base: 0,
+        };
+        dict_set.word_order = dict_set.words().collect();
+        dict_set
+    }
+
+    /// Moves the words of this dictionary set up to start at the given byte, rather than
+    /// at `FIRST_WORD_BYTE`, so that the bytes between are never written and are free for a
+    /// container format to use as its own tags. Control opcodes keep their bytes. Only
+    /// `256 - byte` words fit in a byte, so each byte taken costs a word: the rest cannot be
+    /// encoded, and fail with `EncodeError::DictionaryTooLarge`. The encoder and decoder
+    /// must both use this dictionary set. Returns an error if the byte is one of the
+    /// control opcodes, which lie below `FIRST_WORD_BYTE` unless moved by `with_opcode_base`.
+    pub fn with_first_word_byte(mut self, byte: u8) -> Result<Self, DictionaryError> {
+        if (byte as usize) < self.opcode_base as usize + FIRST_WORD_BYTE as usize {
+            return Err(DictionaryError::ByteRangesOverlap {
+                opcode_base: self.opcode_base,
+                first_word_byte: byte,
+            });
+        }
+        self.first_word_byte = byte;
+        Ok(self)
+    }
+
+    /// Moves the control opcodes up by the given amount, so that the bytes below them are
+    /// never written and are free for a container format to use as its own tags, such as
+    /// `0x00` to `0x1f`. If the opcodes would then overlap the words, the words are moved up
+    /// to the byte just after the last opcode, as if by `with_first_word_byte`, and each
+    /// byte taken from the words costs a word, as described there. The encoder and decoder
+    /// must both use this dictionary set. Returns an error if the opcodes would not fit
+    /// below `0xff` with room for at least one word.
+    pub fn with_opcode_base(mut self, base: u8) -> Result<Self, DictionaryError> {
+        let end = base as usize + FIRST_WORD_BYTE as usize;
+        if end > u8::MAX as usize {
+            return Err(DictionaryError::ByteRangesOverlap {
+                opcode_base: base,
+                first_word_byte: self.first_word_byte,
+            });
+        }
+        self.opcode_base = base;
+        self.first_word_byte = self.first_word_byte.max(end as u8);
+        Ok(self)
+    }
+
+    /// Returns the byte that represents the first word, which is `FIRST_WORD_BYTE` unless
+    /// changed by `with_first_word_byte` or `with_opcode_base`. Every byte from this value
+    /// upwards is a word.
+    pub fn first_word_byte(&self) -> u8 {
+        self.first_word_byte
+    }
+
+    /// Returns the amount added to the byte of every control opcode, which is 0 unless
+    /// changed by `with_opcode_base`.
+    pub fn opcode_base(&self) -> u8 {
+        self.opcode_base
+    }
+
+    /// Returns the byte that the given control opcode is written as in this dictionary set.
+    pub fn opcode_byte(&self, opcode: Opcode) -> u8 {
+        self.opcode_base + opcode as u8
+    }
+
+    /// Returns the control opcode that the given byte is written as in this dictionary set,
+    /// the inverse of `opcode_byte`, or None if the byte is not an opcode.
+    pub fn opcode(&self, byte: u8) -> Option<Opcode> {
+        Opcode::try_from(byte.checked_sub(self.opcode_base)?).ok()
+    }
+
     /// Looks up a toki pona word, written in the given variation.
     /// If this lookup fails, the lookup will be retried in the default orthography.
     pub fn get_identifier_variation(
         &self,
         word: &str,
         variation: Variation,
+    ) -> Option<WordIdentifier> {
+        self.get_identifier_strict(word, variation)
+            .or_else(|| self.get_identifier(word))
+    }
+
+    /// Looks up a toki pona word, written in exactly the given variation.
+    /// Unlike `get_identifier_variation`, the lookup is not retried in the default
+    /// orthography, so a word written in the wrong script is not found.
+    pub fn get_identifier_strict(
+        &self,
+        word: &str,
+        variation: Variation,
     ) -> Option<WordIdentifier> {
         if variation == Variation::Default {
             return self.get_identifier(word);
@@ -51,10 +258,25 @@ impl<'a> DictionarySet<'a> {
                 }
             }
         }
-        self.get_identifier(word)
+        None
+    }
+
+    /// Iterates over every word in the dictionary set, in the order of their bytes.
+    pub fn words(&self) -> impl Iterator<Item = WordIdentifier> + '_ {
+        self.base_dictionaries
+            .iter()
+            .enumerate()
+            .flat_map(|(dict_idx, dict)| {
+                (0..dict.default.words.len()).map(move |word_idx| WordIdentifier {
+                    dict: dict_idx,
+                    word: word_idx,
+                })
+            })
     }
 
     /// Looks up a toki pona word, written in the default orthography.
+    /// Dictionary words are always lower case, so capitalised text is not found;
+    /// the encoder's `sentence_case` and `preserve_case` options lower case it first.
     pub fn get_identifier(&self, word: &str) -> Option<WordIdentifier> {
         for (dict_idx, dict) in self.base_dictionaries.iter().enumerate() {
             if let Some(result) = dict.default.lookup.get(word) {
@@ -67,50 +289,219 @@ impl<'a> DictionarySet<'a> {
         None
     }
 
+    /// Splits text containing no spaces into a sequence of toki pona words written in the
+    /// given variation, such as `"tokipona"` into `toki` and `pona`.
+    /// The longest matching word is preferred at each position, but shorter matches are
+    /// tried if the rest of the text cannot be split after the longer match.
+    /// If there is no way to split the text into words, None is returned.
+    pub fn segment(&self, text: &str, variation: Variation) -> Option<Vec<WordIdentifier>> {
+        self.segment_with(text, &[variation, Variation::Default])
+    }
+
+    /// Splits text into words as `segment` does, but every word must be written in exactly
+    /// the given variation, as for `get_identifier_strict`.
+    pub fn segment_strict(&self, text: &str, variation: Variation) -> Option<Vec<WordIdentifier>> {
+        self.segment_with(text, &[variation])
+    }
+
+    /// Returns the number of characters in the longest spelling of any word in the given
+    /// variation, or 0 if no word is spelt in it. This is computed when the dictionary set
+    /// is created, so it is shared by every encoder using the set.
+    pub fn longest_spelling(&self, variation: Variation) -> usize {
+        self.longest_spelling.get(&variation).copied().unwrap_or(0)
+    }
+
+    /// Splits text into words spelt in any of the given variations. If a spelling is a word
+    /// in more than one of them, the word from the first is used.
+    fn segment_with(&self, text: &str, variations: &[Variation]) -> Option<Vec<WordIdentifier>> {
+        if text.is_empty() {
+            return None;
+        }
+        // After the loop, next[i] is the end of the longest word starting at byte i that
+        // leaves text which can itself be split into words, and that word.
+        // This is filled from the end of the text backwards, so no recursion is needed.
+        let mut next = vec![None; text.len()];
+        for start in (0..text.len()).rev() {
+            if !text.is_char_boundary(start) {
+                continue;
+            }
+            let mut best = None;
+            for trie in variations.iter().filter_map(|v| self.spellings.get(v)) {
+                for (len, word) in trie.prefixes(&text[start..]) {
+                    let end = start + len;
+                    let splits = end == text.len() || next[end].is_some();
+                    if splits && !matches!(best, Some((best_end, _)) if best_end >= end) {
+                        best = Some((end, word));
+                    }
+                }
+            }
+            next[start] = best;
+        }
+
+        let mut words = Vec::new();
+        let mut start = 0;
+        while start != text.len() {
+            let (end, word) = next[start]?;
+            words.push(word);
+            start = end;
+        }
+        Some(words)
+    }
+
     /// Looks up a word identifier and returns the toki pona word in the given orthography.
+    /// If the word has no spelling in that variation, it is given in the default orthography.
+    /// Panics if the identifier is not from this dictionary set.
     pub fn get_word_variation(&self, identifier: WordIdentifier, variation: Variation) -> &'a str {
+        self.get_word_variation_opt(identifier, variation)
+            .unwrap_or_else(|| self.word_str(identifier))
+    }
+
+    /// Looks up a word identifier and returns the toki pona word in the default orthography.
+    /// Panics if the identifier is not from this dictionary set.
+    pub fn word_str(&self, identifier: WordIdentifier) -> &'a str {
+        self.base_dictionaries[identifier.dict].default.words[identifier.word]
+    }
+
+    /// Looks up a word identifier and returns the toki pona word in the given orthography,
+    /// or None if the word has no spelling in that variation, such as a word without a
+    /// sitelen pona glyph. Every word has a spelling in the default orthography.
+    /// Panics if the identifier is not from this dictionary set.
+    pub fn get_word_variation_opt(
+        &self,
+        identifier: WordIdentifier,
+        variation: Variation,
+    ) -> Option<&'a str> {
         let dict = self.base_dictionaries[identifier.dict];
         if variation == Variation::Default {
-            dict.default.words[identifier.word]
-        } else if let Some(word) = dict.variations[&variation].words[identifier.word] {
-            word
+            Some(dict.default.words[identifier.word])
         } else {
-            dict.default.words[identifier.word]
+            dict.variations
+                .get(&variation)
+                .and_then(|variation_dict| variation_dict.words[identifier.word])
         }
     }
 
+    /// Lists the spelling of a word in every variation that has one, starting with the
+    /// default orthography, in the order of the variations' byte codes.
+    /// Panics if the identifier is not from this dictionary set.
+    pub fn variations_for(&self, identifier: WordIdentifier) -> Vec<(Variation, &'a str)> {
+        let dict = self.base_dictionaries[identifier.dict];
+        let mut variations = dict
+            .variations
+            .iter()
+            .filter_map(|(variation, variation_dict)| {
+                Some((*variation, variation_dict.words[identifier.word]?))
+            })
+            .collect::<Vec<_>>();
+        variations.push((Variation::Default, dict.default.words[identifier.word]));
+        variations.sort_by_key(|(variation, _)| u8::from(*variation));
+        variations
+    }
+
     /// Returns a list of bytes representing this word.
-    pub fn word_to_bytes(&self, word: WordIdentifier) -> Vec<u8> {
-        if word.dict < self.base_dictionaries.len() {
-            let index = self
-                .base_dictionaries
-                .iter()
-                .take(word.dict)
-                .map(|dict| dict.default.words.len())
-                .sum::<usize>()
-                + word.word;
-            vec![0x22 + u8::try_from(index).expect("index too large")]
+    /// If the dictionary set is too large for this word to fit in the byte space,
+    /// or the word is not in this dictionary set, an error is returned.
+    pub fn word_to_bytes(&self, word: WordIdentifier) -> Result<Vec<u8>, EncodeError> {
+        let index = self
+            .flat_index(word)
+            .ok_or(EncodeError::UnknownIdentifier(word))?;
+        u8::try_from(index)
+            .ok()
+            .and_then(|byte| byte.checked_add(self.first_word_byte))
+            .map(|byte| vec![byte])
+            .ok_or(EncodeError::DictionaryTooLarge { index })
+    }
+
+    /// Returns the word identifier represented by these bytes, the inverse of
+    /// `word_to_bytes`. If no word is represented by these bytes, None is returned.
+    pub fn word_from_bytes(&self, bytes: &[u8]) -> Option<WordIdentifier> {
+        if let [byte] = bytes {
+            // This is a single-byte word, which must be in the base dictionaries.
+            self.from_flat_index(byte.checked_sub(self.first_word_byte)? as usize)
         } else {
-            todo!()
+            // Only single-byte words exist.
+            None
         }
     }
 
-    /// Returns the word identifier representing this word.
-    pub fn word_from_bytes(&self, bytes: &[u8]) -> WordIdentifier {
-        if bytes.len() == 1 {
-            // This is a single-byte word, which must be in the base dictionaries.
-            let mut index = bytes[0] as usize;
-            let mut dict_index = 0;
-            while index >= self.base_dictionaries[dict_index].default.words.len() {
-                index -= self.base_dictionaries[dict_index].default.words.len();
-                dict_index += 1;
+    /// Returns the index of this word among every word in the dictionary set, in the order
+    /// of their bytes, which is a single integer that identifies the word.
+    /// If the word is not in this dictionary set, None is returned.
+    pub fn flat_index(&self, word: WordIdentifier) -> Option<usize> {
+        let dict = self.base_dictionaries.get(word.dict)?;
+        (word.word < dict.default.words.len()).then(|| self.offsets[word.dict] + word.word)
+    }
+
+    /// Returns the word with this index among every word in the dictionary set, the inverse
+    /// of `flat_index`. If there are not this many words, None is returned.
+    pub fn from_flat_index(&self, index: usize) -> Option<WordIdentifier> {
+        self.word_order.get(index).copied()
+    }
+
+    /// Returns the largest number of bytes that any single word encodes to.
+    /// This is the worst-case size of a word instruction, ignoring the words that
+    /// cannot be encoded because the dictionary set is too large.
+    /// Returns 0 if the dictionary set has no words.
+    pub fn max_word_bytes(&self) -> usize {
+        self.words()
+            .filter_map(|word| self.word_to_bytes(word).ok())
+            .map(|bytes| bytes.len())
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Returns the mean number of bytes that each word encodes to, with every word in
+    /// the dictionary set weighted equally. Real text, which uses some words much more
+    /// than others, may differ. Returns 0 if the dictionary set has no words.
+    pub fn average_word_bytes(&self) -> f64 {
+        let (count, total) = self
+            .words()
+            .filter_map(|word| self.word_to_bytes(word).ok())
+            .fold((0, 0), |(count, total), bytes| {
+                (count + 1, total + bytes.len())
+            });
+        if count == 0 {
+            0.0
+        } else {
+            total as f64 / count as f64
+        }
+    }
+
+    /// Lists every word in the order of their bytes, with the number of bytes its spelling
+    /// takes in UTF-8 in the default orthography. Comparing this with the size of its
+    /// encoding from `word_to_bytes` shows how many bytes each word saves, which, with how
+    /// often the words are used, informs which words deserve single-byte encodings.
+    pub fn savings_report(&self) -> Vec<(WordIdentifier, usize)> {
+        self.words()
+            .map(|word| (word, self.word_str(word).len()))
+            .collect()
+    }
+
+    /// Lists the meaning of every possible leading byte of an instruction.
+    /// Panics if two features claim the same byte.
+    pub fn byte_map(&self) -> BTreeMap<u8, ByteMeaning> {
+        let mut map = BTreeMap::new();
+        let mut claim = |byte: u8, meaning: ByteMeaning| {
+            if let Some(previous) = map.insert(byte, meaning) {
+                panic!(
+                    "byte {:#x?} claimed by both {:?} and {:?}",
+                    byte, previous, meaning
+                );
             }
-            WordIdentifier {
-                dict: dict_index,
-                word: index,
+        };
+
+        for opcode in Opcode::ALL {
+            claim(self.opcode_byte(*opcode), ByteMeaning::Opcode(*opcode));
+        }
+        for word in self.words() {
+            if let Ok(bytes) = self.word_to_bytes(word) {
+                claim(bytes[0], ByteMeaning::Word(word));
             }
-        } else {
-            todo!()
         }
+
+        for byte in 0..=u8::MAX {
+            map.entry(byte).or_insert(ByteMeaning::Free);
+        }
+        map
     }
 }
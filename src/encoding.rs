@@ -1,78 +1,1403 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
 use std::fmt;
 use std::io;
+use std::ops::Range;
 
 use crate::{
-    dict_set::{WordIdentifier, DICT_SET},
+    abbreviations::{self, ABBREVIATIONS},
+    base64,
+    dict_set::{DictionarySet, WordIdentifier, DICT_SET},
+    error::{DecodeError, EncodeError},
+    numerals::NumeralSystem,
+    phrases::{self, PHRASES},
+    punctuation::Punctuation,
+    tokenizer::{
+        continues_grapheme, ends_punctuation, is_clinging_punctuation, split_emoji,
+        strip_punctuation, Piece,
+    },
     variation::Variation,
 };
 
+/// The first byte used to represent a single-byte word, unless a dictionary set moves its
+/// words up with `DictionarySet::with_first_word_byte` or `DictionarySet::with_opcode_base`.
+/// Every byte from this value upwards is a word; control opcodes lie below it.
+pub const FIRST_WORD_BYTE: u8 = 0x22;
+
+/// The most words that a Cartouche instruction may hold, so that a decoder waiting for the
+/// end of a cartouche never keeps more than a few hundred bytes.
+pub const MAX_CARTOUCHE_WORDS: usize = 255;
+
+/// The leading byte of each control instruction, unless a dictionary set moves the
+/// opcodes up with `DictionarySet::with_opcode_base`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u8)]
+pub enum Opcode {
+    EndOfStream = 0x00,
+    Abbreviation = 0x0c,
+    CartoucheOpen = 0x0d,
+    CartoucheClose = 0x0e,
+    CodeSpan = 0x0f,
+    GroupOpen = 0x10,
+    GroupClose = 0x11,
+    VariationHint = 0x12,
+    ExplicitSpaces = 0x13,
+    Space = 0x14,
+    Phrase = 0x15,
+    SpeltWord = 0x16,
+    PunctuationRun = 0x17,
+    CaseMask = 0x18,
+    Capitalize = 0x19,
+    RawText = 0x1a,
+    ExclamationMark = 0x1b,
+    QuestionMark = 0x1c,
+    Colon = 0x1d,
+    Comma = 0x1e,
+    FullStop = 0x1f,
+    SetVariation = 0x20,
+    AttachToPrevious = 0x21,
+}
+
+impl Opcode {
+    /// Every control opcode, in no particular order.
+    pub const ALL: &'static [Opcode] = &[
+        Opcode::EndOfStream,
+        Opcode::Abbreviation,
+        Opcode::CartoucheOpen,
+        Opcode::CartoucheClose,
+        Opcode::CodeSpan,
+        Opcode::GroupOpen,
+        Opcode::GroupClose,
+        Opcode::VariationHint,
+        Opcode::ExplicitSpaces,
+        Opcode::Space,
+        Opcode::Phrase,
+        Opcode::SpeltWord,
+        Opcode::PunctuationRun,
+        Opcode::CaseMask,
+        Opcode::Capitalize,
+        Opcode::RawText,
+        Opcode::ExclamationMark,
+        Opcode::QuestionMark,
+        Opcode::Colon,
+        Opcode::Comma,
+        Opcode::FullStop,
+        Opcode::SetVariation,
+        Opcode::AttachToPrevious,
+    ];
+}
+
+impl From<Punctuation> for Opcode {
+    fn from(value: Punctuation) -> Self {
+        match value {
+            Punctuation::FullStop => Opcode::FullStop,
+            Punctuation::Comma => Opcode::Comma,
+            Punctuation::Colon => Opcode::Colon,
+            Punctuation::QuestionMark => Opcode::QuestionMark,
+            Punctuation::ExclamationMark => Opcode::ExclamationMark,
+        }
+    }
+}
+
+impl TryFrom<Opcode> for Punctuation {
+    type Error = ();
+
+    fn try_from(value: Opcode) -> Result<Self, Self::Error> {
+        Ok(match value {
+            Opcode::FullStop => Punctuation::FullStop,
+            Opcode::Comma => Punctuation::Comma,
+            Opcode::Colon => Punctuation::Colon,
+            Opcode::QuestionMark => Punctuation::QuestionMark,
+            Opcode::ExclamationMark => Punctuation::ExclamationMark,
+            _ => return Err(()),
+        })
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Opcode::ALL
+            .iter()
+            .copied()
+            .find(|opcode| *opcode as u8 == value)
+            .ok_or(())
+    }
+}
+
 /// A passage of text is comprised of a list of instructions.
 /// Each instruction may write some Unicode output, or alter some internal state.
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Instruction {
     /// Print a word in toki pona from the main dictionary set
     /// using the active variation.
     TokiPonaWord { word: WordIdentifier },
+    /// Print each word of the phrase with this code in `phrases::PHRASES`,
+    /// as if each were a TokiPonaWord instruction.
+    Phrase { code: u8 },
+    /// Print the expansion of the shorthand with this code in
+    /// `abbreviations::ABBREVIATIONS`, such as `toki pona` for `tp`, as if it were a
+    /// Phrase instruction, or the shorthand itself, spaced like a word, if the decoder
+    /// contracts abbreviations.
+    Abbreviation { code: u8 },
     /// Instead of prepending a space before the subsequent word, attach the words together.
     AttachToPrevious,
+    /// Render subsequent words using the given variation.
+    SetVariation { variation: Variation },
+    /// States that the message is written in the given variation, before any words.
+    /// This renders subsequent words in that variation like SetVariation, but also lets a
+    /// decoder tell which script the message is in; see `Decoder::variation_hint`.
+    VariationHint { variation: Variation },
+    /// Print a punctuation mark directly after the previous word.
+    Punctuation { mark: Punctuation },
+    /// Print a run of punctuation directly after the previous word, such as `:)` or `?!"`.
+    /// This covers punctuation with no instruction of its own.
+    PunctuationRun { text: String },
+    /// Print some text that is not in the dictionary set, such as an emoji.
+    /// This is spaced like a word.
+    RawText { text: String },
+    /// Print a span of text that is not toki pona, such as code, including its delimiters,
+    /// such as `` `fn main` ``. This is spaced like a word. Unlike RawText, it may contain
+    /// spaces; see the encoder's `code_delimiter` option.
+    CodeSpan { text: String },
+    /// Print a name written as a cartouche of these words, as in sitelen pona, where each
+    /// word stands for its first letter. In sitelen pona, the glyphs are printed between
+    /// the UCSUR cartouche code points; otherwise the name is spelt out from the first
+    /// letter of each word, such as `Sonja` for `sona olin nasin jan awen`. This is
+    /// spaced like a word. The words are written between a CartoucheOpen and a
+    /// CartoucheClose byte, a byte each, in every layout.
+    Cartouche { words: Vec<WordIdentifier> },
+    /// Print the next word with its first letter capitalised, as at the start of a sentence.
+    Capitalize,
+    /// Print the next word with the letters given by this bitmask in upper case.
+    /// Bit `i % 8` of byte `i / 8` is set if letter `i` is upper case.
+    CaseMask { mask: Vec<u8> },
+    /// Print an opening bracket `(` that groups the words after it, such as the words of a
+    /// `pi` phrase. This is spaced like a word, and the next word is attached to it.
+    GroupOpen,
+    /// Print a closing bracket `)` directly after the previous word, ending a group.
+    GroupClose,
+    /// Print a space. This is only needed after an ExplicitSpaces instruction.
+    Space,
+    /// From now until the end of the message, words are not spaced automatically, so every
+    /// space is a Space instruction and AttachToPrevious is never needed.
+    ExplicitSpaces,
+    /// Marks the end of a message, so that several messages can be sent in one stream.
+    /// The decoder's state is reset, as if the next byte were the start of a new stream.
+    EndOfStream,
 }
 
 impl Instruction {
-    fn encode(&self) -> Vec<u8> {
+    fn encode(&self, dict_set: &DictionarySet, layout: Layout) -> Result<Vec<u8>, EncodeError> {
+        Ok(match self {
+            Instruction::TokiPonaWord { word } if layout == Layout::Debug => {
+                // Words too far into the dictionary set for a single byte can still be spelt.
+                if let Err(err @ EncodeError::UnknownIdentifier(_)) = dict_set.word_to_bytes(*word)
+                {
+                    return Err(err);
+                }
+                // The word's spelling is prefixed with its length in bytes.
+                let text = dict_set.word_str(*word);
+                let len = u8::try_from(text.len())
+                    .map_err(|_| EncodeError::RawTextTooLong { len: text.len() })?;
+                let mut bytes = vec![dict_set.opcode_byte(Opcode::SpeltWord), len];
+                bytes.extend_from_slice(text.as_bytes());
+                bytes
+            }
+            Instruction::TokiPonaWord { word } => dict_set.word_to_bytes(*word)?,
+            Instruction::Phrase { code } => vec![dict_set.opcode_byte(Opcode::Phrase), *code],
+            Instruction::Abbreviation { code } => {
+                vec![dict_set.opcode_byte(Opcode::Abbreviation), *code]
+            }
+            Instruction::AttachToPrevious => vec![dict_set.opcode_byte(Opcode::AttachToPrevious)],
+            Instruction::SetVariation { variation } => {
+                vec![
+                    dict_set.opcode_byte(Opcode::SetVariation),
+                    u8::from(*variation),
+                ]
+            }
+            Instruction::VariationHint { variation } => {
+                vec![
+                    dict_set.opcode_byte(Opcode::VariationHint),
+                    u8::from(*variation),
+                ]
+            }
+            Instruction::Punctuation { mark } => vec![dict_set.opcode_byte(Opcode::from(*mark))],
+            Instruction::PunctuationRun { text } => {
+                // The text is prefixed with its length in bytes.
+                let len = u8::try_from(text.len())
+                    .map_err(|_| EncodeError::RawTextTooLong { len: text.len() })?;
+                let mut bytes = vec![dict_set.opcode_byte(Opcode::PunctuationRun), len];
+                bytes.extend_from_slice(text.as_bytes());
+                bytes
+            }
+            Instruction::RawText { text } => {
+                // The text is prefixed with its length in bytes.
+                let len = u8::try_from(text.len())
+                    .map_err(|_| EncodeError::RawTextTooLong { len: text.len() })?;
+                let mut bytes = vec![dict_set.opcode_byte(Opcode::RawText), len];
+                bytes.extend_from_slice(text.as_bytes());
+                bytes
+            }
+            Instruction::CodeSpan { text } => {
+                // The text is prefixed with its length in bytes.
+                let len = u8::try_from(text.len())
+                    .map_err(|_| EncodeError::RawTextTooLong { len: text.len() })?;
+                let mut bytes = vec![dict_set.opcode_byte(Opcode::CodeSpan), len];
+                bytes.extend_from_slice(text.as_bytes());
+                bytes
+            }
+            Instruction::Cartouche { words } => {
+                if words.len() > MAX_CARTOUCHE_WORDS {
+                    return Err(EncodeError::CartoucheTooLong { len: words.len() });
+                }
+                let mut bytes = vec![dict_set.opcode_byte(Opcode::CartoucheOpen)];
+                for word in words {
+                    bytes.extend(dict_set.word_to_bytes(*word)?);
+                }
+                bytes.push(dict_set.opcode_byte(Opcode::CartoucheClose));
+                bytes
+            }
+            Instruction::Capitalize => vec![dict_set.opcode_byte(Opcode::Capitalize)],
+            Instruction::CaseMask { mask } => {
+                // The mask is prefixed with its length in bytes.
+                let len = u8::try_from(mask.len())
+                    .map_err(|_| EncodeError::RawTextTooLong { len: mask.len() })?;
+                let mut bytes = vec![dict_set.opcode_byte(Opcode::CaseMask), len];
+                bytes.extend_from_slice(mask);
+                bytes
+            }
+            Instruction::GroupOpen => vec![dict_set.opcode_byte(Opcode::GroupOpen)],
+            Instruction::GroupClose => vec![dict_set.opcode_byte(Opcode::GroupClose)],
+            Instruction::Space => vec![dict_set.opcode_byte(Opcode::Space)],
+            Instruction::ExplicitSpaces => vec![dict_set.opcode_byte(Opcode::ExplicitSpaces)],
+            Instruction::EndOfStream => vec![dict_set.opcode_byte(Opcode::EndOfStream)],
+        })
+    }
+
+    /// Renders this instruction in a human-readable form for debugging, such as `WORD(toki)`.
+    fn disassemble(&self, dict_set: &DictionarySet) -> String {
+        match self {
+            Instruction::TokiPonaWord { word } => format!("WORD({})", dict_set.word_str(*word)),
+            Instruction::Phrase { code } => format!("PHRASE({})", PHRASES[*code as usize]),
+            Instruction::Abbreviation { code } => {
+                format!("ABBREVIATION({})", ABBREVIATIONS[*code as usize].0)
+            }
+            Instruction::AttachToPrevious => "ATTACH".to_owned(),
+            Instruction::SetVariation { variation } => {
+                format!("SET_VARIATION({})", <&str>::from(*variation))
+            }
+            Instruction::VariationHint { variation } => {
+                format!("VARIATION_HINT({})", <&str>::from(*variation))
+            }
+            Instruction::Punctuation { mark } => format!("PUNCTUATION({})", char::from(*mark)),
+            Instruction::PunctuationRun { text } => format!("PUNCTUATION_RUN({:?})", text),
+            Instruction::RawText { text } => format!("RAW_TEXT({:?})", text),
+            Instruction::CodeSpan { text } => format!("CODE_SPAN({:?})", text),
+            Instruction::Cartouche { words } => format!(
+                "CARTOUCHE({})",
+                words
+                    .iter()
+                    .map(|word| dict_set.word_str(*word))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ),
+            Instruction::Capitalize => "CAPITALIZE".to_owned(),
+            Instruction::CaseMask { mask } => format!(
+                "CASE_MASK({})",
+                (0..mask.len() * 8)
+                    .map(|i| if mask[i / 8] & 1 << (i % 8) != 0 {
+                        '1'
+                    } else {
+                        '0'
+                    })
+                    .collect::<String>()
+            ),
+            Instruction::GroupOpen => "GROUP_OPEN".to_owned(),
+            Instruction::GroupClose => "GROUP_CLOSE".to_owned(),
+            Instruction::Space => "SPACE".to_owned(),
+            Instruction::ExplicitSpaces => "EXPLICIT_SPACES".to_owned(),
+            Instruction::EndOfStream => "END_OF_STREAM".to_owned(),
+        }
+    }
+
+    /// Returns the dictionary words that this instruction prints: the word of a
+    /// TokiPonaWord instruction, or the words that a Phrase or Abbreviation instruction
+    /// stands for. Other instructions print no dictionary words, and return an empty list.
+    /// Returns an error if a phrase or abbreviation is not known, or has a word that is not
+    /// in the dictionary set.
+    pub fn expanded_words(
+        &self,
+        dict_set: &DictionarySet,
+    ) -> Result<Vec<WordIdentifier>, DecodeError> {
         match self {
-            Instruction::TokiPonaWord { word } => DICT_SET.word_to_bytes(*word),
-            Instruction::AttachToPrevious => vec![0x21],
+            Instruction::TokiPonaWord { word } => Ok(vec![*word]),
+            Instruction::Phrase { code } => {
+                phrases::words(dict_set, *code).ok_or(DecodeError::UnknownPhrase(*code))
+            }
+            Instruction::Abbreviation { code } => {
+                abbreviations::words(dict_set, *code).ok_or(DecodeError::UnknownAbbreviation(*code))
+            }
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    /// Parses the instruction at the start of the given bytes, returning the instruction
+    /// and the number of bytes it occupied.
+    /// If the bytes end partway through an instruction, None is returned.
+    fn decode(
+        bytes: &[u8],
+        dict_set: &DictionarySet,
+    ) -> Result<Option<(Instruction, usize)>, DecodeError> {
+        let byte = match bytes.first() {
+            Some(byte) => *byte,
+            None => return Ok(None),
+        };
+        if byte >= dict_set.first_word_byte() {
+            // This is a single-byte toki pona word.
+            let word = dict_set
+                .word_from_bytes(&[byte])
+                .ok_or(DecodeError::UnexpectedByte(byte))?;
+            return Ok(Some((Instruction::TokiPonaWord { word }, 1)));
+        }
+
+        Ok(Some(match dict_set.opcode(byte).ok_or(()) {
+            Ok(opcode @ (Opcode::SetVariation | Opcode::VariationHint)) => {
+                let variation = match bytes.get(1) {
+                    Some(variation) => *variation,
+                    None => return Ok(None),
+                };
+                let variation = variation
+                    .try_into()
+                    .map_err(|()| DecodeError::UnknownVariation(variation))?;
+                if opcode == Opcode::SetVariation {
+                    (Instruction::SetVariation { variation }, 2)
+                } else {
+                    (Instruction::VariationHint { variation }, 2)
+                }
+            }
+            Ok(opcode @ (Opcode::Phrase | Opcode::Abbreviation)) => {
+                let code = match bytes.get(1) {
+                    Some(code) => *code,
+                    None => return Ok(None),
+                };
+                let instruction = if opcode == Opcode::Phrase {
+                    Instruction::Phrase { code }
+                } else {
+                    Instruction::Abbreviation { code }
+                };
+                // Every word that the code stands for must be in the dictionary set.
+                instruction.expanded_words(dict_set)?;
+                (instruction, 2)
+            }
+            Ok(Opcode::AttachToPrevious) => (Instruction::AttachToPrevious, 1),
+            Ok(Opcode::Capitalize) => (Instruction::Capitalize, 1),
+            Ok(Opcode::CaseMask) => {
+                let len = match bytes.get(1) {
+                    Some(len) => *len as usize,
+                    None => return Ok(None),
+                };
+                let mask = match bytes.get(2..2 + len) {
+                    Some(mask) => mask.to_vec(),
+                    None => return Ok(None),
+                };
+                (Instruction::CaseMask { mask }, 2 + len)
+            }
+            Ok(Opcode::GroupOpen) => (Instruction::GroupOpen, 1),
+            Ok(Opcode::GroupClose) => (Instruction::GroupClose, 1),
+            Ok(Opcode::Space) => (Instruction::Space, 1),
+            Ok(Opcode::ExplicitSpaces) => (Instruction::ExplicitSpaces, 1),
+            Ok(Opcode::EndOfStream) => (Instruction::EndOfStream, 1),
+            Ok(Opcode::RawText) => match decode_text(bytes)? {
+                Some((text, len)) => (
+                    Instruction::RawText {
+                        text: text.to_owned(),
+                    },
+                    len,
+                ),
+                None => return Ok(None),
+            },
+            Ok(Opcode::CodeSpan) => match decode_text(bytes)? {
+                Some((text, len)) => (
+                    Instruction::CodeSpan {
+                        text: text.to_owned(),
+                    },
+                    len,
+                ),
+                None => return Ok(None),
+            },
+            Ok(Opcode::CartoucheOpen) => {
+                // The words run until the closing byte.
+                // Any other byte is an error for the whole cartouche, rather than an
+                // unexpected byte, which would be skipped on its own.
+                let mut words = Vec::new();
+                for (i, &byte) in bytes.iter().enumerate().skip(1) {
+                    if byte == dict_set.opcode_byte(Opcode::CartoucheClose) {
+                        return Ok(Some((Instruction::Cartouche { words }, i + 1)));
+                    }
+                    if words.len() == MAX_CARTOUCHE_WORDS {
+                        return Err(DecodeError::InvalidCartouche);
+                    }
+                    words.push(
+                        dict_set
+                            .word_from_bytes(&[byte])
+                            .ok_or(DecodeError::InvalidCartouche)?,
+                    );
+                }
+                return Ok(None);
+            }
+            Ok(Opcode::SpeltWord) => match decode_text(bytes)? {
+                Some((text, len)) => {
+                    let word = dict_set
+                        .get_identifier(text)
+                        .ok_or_else(|| DecodeError::UnknownSpelling(text.to_owned()))?;
+                    (Instruction::TokiPonaWord { word }, len)
+                }
+                None => return Ok(None),
+            },
+            Ok(Opcode::PunctuationRun) => match decode_text(bytes)? {
+                Some((text, len)) => (
+                    Instruction::PunctuationRun {
+                        text: text.to_owned(),
+                    },
+                    len,
+                ),
+                None => return Ok(None),
+            },
+            Ok(
+                opcode @ (Opcode::FullStop
+                | Opcode::Comma
+                | Opcode::Colon
+                | Opcode::QuestionMark
+                | Opcode::ExclamationMark),
+            ) => (
+                Instruction::Punctuation {
+                    mark: opcode.try_into().unwrap(),
+                },
+                1,
+            ),
+            // A cartouche can only be closed after it is opened.
+            Ok(Opcode::CartoucheClose) | Err(()) => return Err(DecodeError::UnexpectedByte(byte)),
+        }))
+    }
+}
+
+/// Parses the text of an instruction that begins with an opcode and the text's length
+/// in bytes, returning the text and the number of bytes the instruction occupied.
+/// If the bytes end partway through the text, None is returned.
+/// The length is a single byte, so it is bounded by 255 without needing a varint: the
+/// encoder rejects longer text with `RawTextTooLong`, and the decoder only ever slices
+/// bytes it already has, so a large claimed length cannot make it read or allocate more.
+fn decode_text(bytes: &[u8]) -> Result<Option<(&str, usize)>, DecodeError> {
+    let len = match bytes.get(1) {
+        Some(len) => *len as usize,
+        None => return Ok(None),
+    };
+    match bytes.get(2..2 + len) {
+        Some(text) => {
+            let text = std::str::from_utf8(text).map_err(|_| DecodeError::InvalidUtf8)?;
+            Ok(Some((text, 2 + len)))
         }
+        None => Ok(None),
+    }
+}
+
+/// Returns the error for bytes that end partway through an instruction:
+/// `TruncatedPayload` if the instruction's length claims more bytes than remain,
+/// or `TruncatedStream` otherwise.
+fn truncation_error(bytes: &[u8], dict_set: &DictionarySet) -> DecodeError {
+    let opcode = bytes.first().map(|&byte| dict_set.opcode(byte));
+    match (opcode, bytes.get(1)) {
+        (
+            Some(Some(
+                Opcode::RawText
+                | Opcode::PunctuationRun
+                | Opcode::SpeltWord
+                | Opcode::CaseMask
+                | Opcode::CodeSpan,
+            )),
+            Some(&len),
+        ) => DecodeError::TruncatedPayload {
+            claimed: len as usize,
+            remaining: bytes.len() - 2,
+        },
+        _ => DecodeError::TruncatedStream,
     }
 }
 
+/// How a decoder chooses which variation to render words in.
+#[derive(Debug, Default, Clone, Copy)]
+enum Rendering {
+    /// Render words in the variation set by the stream.
+    #[default]
+    Active,
+    /// Always render words in this variation.
+    Override(Variation),
+    /// Render each word in the first variation, followed by the second in parentheses.
+    Interlinear(Variation, Variation),
+    /// Render each word in this variation as HTML ruby text, annotated with the word in
+    /// the default orthography.
+    Ruby(Variation),
+}
+
 /// The internal state of an encoder or decoder.
 #[derive(Debug, Default)]
 struct EncodingState {
     /// What orthography variation is active?
     variation: Variation,
     /// If this is true, before printing the next TokiPonaWord, a space will be prepended.
+    /// Punctuation is never preceded by a space, but it sets this flag
+    /// so that the word after it is spaced normally.
     prepend_space: bool,
+    /// If this is true, the next word is printed with its first letter capitalised.
+    capitalize_next: bool,
+    /// If this is present, the next word is printed with these letters in upper case.
+    case_mask: Option<Vec<u8>>,
+    /// If this is true, spaces are only printed by Space instructions, so prepend_space
+    /// is never set.
+    explicit_spaces: bool,
+    /// The last word printed, if nothing but a space has been printed after it.
+    previous_word: Option<WordIdentifier>,
+    /// If this is present, a word rendered in sitelen pona that repeats the previous word
+    /// is printed as this marker instead.
+    reduplication_marker: Option<&'static str>,
+    /// If this is true, abbreviations are printed as their shorthand, not their words.
+    contract_abbreviations: bool,
+    /// If this is present, it is printed before every word but the first of a message.
+    word_boundary: Option<&'static str>,
+    /// True if a word has been printed since the start of the message.
+    word_printed: bool,
+    /// Words that are printed as these glyphs instead of their spelling in any variation.
+    glyph_overrides: HashMap<WordIdentifier, String>,
+    /// The variation that is active at the start of each message, before any SetVariation.
+    initial_variation: Variation,
+}
+
+impl EncodingState {
+    /// Updates the state for the given instruction, and outputs the text it produces.
+    /// Any error from the output function stops execution and is returned.
+    fn execute<'d>(
+        &mut self,
+        instruction: Instruction,
+        dict_set: &DictionarySet<'d>,
+        rendering: Rendering,
+        mut output: impl FnMut(Cow<'d, str>) -> fmt::Result,
+    ) -> fmt::Result {
+        if !matches!(
+            instruction,
+            Instruction::TokiPonaWord { .. }
+                | Instruction::Phrase { .. }
+                | Instruction::Abbreviation { .. }
+                | Instruction::Space
+        ) {
+            self.previous_word = None;
+        }
+        match instruction {
+            Instruction::TokiPonaWord { word } => {
+                self.execute_word(word, dict_set, rendering, &mut output)?;
+            }
+            Instruction::Abbreviation { code } if self.contract_abbreviations => {
+                if self.prepend_space {
+                    output(Cow::Borrowed(" "))?;
+                }
+                self.mark_word_boundary(&mut output)?;
+                output(Cow::Borrowed(ABBREVIATIONS[code as usize].0))?;
+                self.prepend_space = !self.explicit_spaces;
+                self.capitalize_next = false;
+                self.case_mask = None;
+                self.previous_word = None;
+            }
+            Instruction::Phrase { .. } | Instruction::Abbreviation { .. } => {
+                let words = instruction
+                    .expanded_words(dict_set)
+                    .expect("decoded instructions should be known");
+                for (i, word) in words.into_iter().enumerate() {
+                    // The words of a phrase or an abbreviation are always spaced.
+                    if i > 0 && self.explicit_spaces {
+                        output(Cow::Borrowed(" "))?;
+                    }
+                    self.execute_word(word, dict_set, rendering, &mut output)?;
+                }
+            }
+            Instruction::AttachToPrevious => {
+                self.prepend_space = false;
+            }
+            Instruction::SetVariation { variation } | Instruction::VariationHint { variation } => {
+                self.variation = variation;
+            }
+            Instruction::Punctuation { mark } => {
+                output(Cow::Borrowed(mark.into()))?;
+                self.prepend_space = !self.explicit_spaces;
+            }
+            Instruction::PunctuationRun { text } => {
+                if let Rendering::Ruby(_) = rendering {
+                    output(Cow::Owned(escape_html(&text)))?;
+                } else {
+                    output(Cow::Owned(text))?;
+                }
+                self.prepend_space = !self.explicit_spaces;
+            }
+            Instruction::RawText { text } | Instruction::CodeSpan { text } => {
+                if self.prepend_space {
+                    output(Cow::Borrowed(" "))?;
+                }
+                if let Rendering::Ruby(_) = rendering {
+                    output(Cow::Owned(escape_html(&text)))?;
+                } else {
+                    output(Cow::Owned(text))?;
+                }
+                self.prepend_space = !self.explicit_spaces;
+                self.capitalize_next = false;
+                self.case_mask = None;
+            }
+            Instruction::Cartouche { words } => {
+                if self.prepend_space {
+                    output(Cow::Borrowed(" "))?;
+                }
+                self.mark_word_boundary(&mut output)?;
+                let render = |variation| render_cartouche(&words, dict_set, variation);
+                match rendering {
+                    Rendering::Active => output(Cow::Owned(render(self.variation)))?,
+                    Rendering::Override(variation) => output(Cow::Owned(render(variation)))?,
+                    Rendering::Interlinear(first, second) => {
+                        output(Cow::Owned(render(first)))?;
+                        output(Cow::Borrowed("("))?;
+                        output(Cow::Owned(render(second)))?;
+                        output(Cow::Borrowed(")"))?;
+                    }
+                    Rendering::Ruby(variation) => {
+                        output(Cow::Borrowed("<ruby>"))?;
+                        output(Cow::Owned(render(variation)))?;
+                        output(Cow::Borrowed("<rt>"))?;
+                        output(Cow::Owned(render(Variation::Default)))?;
+                        output(Cow::Borrowed("</rt></ruby>"))?;
+                    }
+                }
+                self.prepend_space = !self.explicit_spaces;
+                self.capitalize_next = false;
+                self.case_mask = None;
+            }
+            Instruction::Capitalize => {
+                self.capitalize_next = true;
+            }
+            Instruction::CaseMask { mask } => {
+                self.case_mask = Some(mask);
+            }
+            Instruction::GroupOpen => {
+                if self.prepend_space {
+                    output(Cow::Borrowed(" "))?;
+                }
+                output(Cow::Borrowed("("))?;
+                self.prepend_space = false;
+            }
+            Instruction::GroupClose => {
+                output(Cow::Borrowed(")"))?;
+                self.prepend_space = !self.explicit_spaces;
+            }
+            Instruction::Space => {
+                output(Cow::Borrowed(" "))?;
+            }
+            Instruction::ExplicitSpaces => {
+                self.explicit_spaces = true;
+                self.prepend_space = false;
+            }
+            Instruction::EndOfStream => {
+                *self = EncodingState {
+                    variation: self.initial_variation,
+                    reduplication_marker: self.reduplication_marker,
+                    word_boundary: self.word_boundary,
+                    contract_abbreviations: self.contract_abbreviations,
+                    glyph_overrides: std::mem::take(&mut self.glyph_overrides),
+                    initial_variation: self.initial_variation,
+                    ..EncodingState::default()
+                };
+            }
+        }
+        Ok(())
+    }
+
+    /// Outputs the word boundary marker, if there is one, before a word that is not the
+    /// first of the message.
+    fn mark_word_boundary<'d>(
+        &mut self,
+        output: &mut impl FnMut(Cow<'d, str>) -> fmt::Result,
+    ) -> fmt::Result {
+        if let Some(marker) = self.word_boundary {
+            if self.word_printed {
+                output(Cow::Borrowed(marker))?;
+            }
+        }
+        self.word_printed = true;
+        Ok(())
+    }
+
+    /// Outputs the text of a single word, as for a TokiPonaWord instruction.
+    fn execute_word<'d>(
+        &mut self,
+        word: WordIdentifier,
+        dict_set: &DictionarySet<'d>,
+        rendering: Rendering,
+        output: &mut impl FnMut(Cow<'d, str>) -> fmt::Result,
+    ) -> fmt::Result {
+        if self.prepend_space {
+            output(Cow::Borrowed(" "))?;
+        }
+        self.mark_word_boundary(output)?;
+        let capitalize = std::mem::take(&mut self.capitalize_next);
+        let case_mask = self.case_mask.take();
+        let repeated = self.previous_word.replace(word) == Some(word);
+        let rendered_variation = match rendering {
+            Rendering::Active => Some(self.variation),
+            Rendering::Override(variation) => Some(variation),
+            Rendering::Interlinear(..) | Rendering::Ruby(_) => None,
+        };
+        if let Some(marker) = self.reduplication_marker {
+            if repeated && rendered_variation == Some(Variation::SitelenPona) {
+                output(Cow::Borrowed(marker))?;
+                self.prepend_space = !self.explicit_spaces;
+                return Ok(());
+            }
+        }
+        // An overriding glyph replaces the word's main spelling, but not a gloss of it.
+        let glyph = self.glyph_overrides.get(&word);
+        let spelling = |variation, overridden: bool| match glyph {
+            Some(glyph) if overridden => Cow::Owned(glyph.clone()),
+            _ => Cow::Borrowed(dict_set.get_word_variation(word, variation)),
+        };
+        let render = |variation, overridden| {
+            let word = spelling(variation, overridden);
+            if let Some(mask) = &case_mask {
+                Cow::Owned(apply_case_mask(&word, mask))
+            } else if capitalize {
+                Cow::Owned(capitalize_first(&word))
+            } else {
+                word
+            }
+        };
+        match rendering {
+            Rendering::Active => output(render(self.variation, true))?,
+            Rendering::Override(variation) => output(render(variation, true))?,
+            Rendering::Interlinear(first, second) => {
+                output(render(first, true))?;
+                output(Cow::Borrowed("("))?;
+                output(spelling(second, false))?;
+                output(Cow::Borrowed(")"))?;
+            }
+            Rendering::Ruby(variation) => {
+                output(Cow::Borrowed("<ruby>"))?;
+                // Unlike the dictionary's spellings, an override may contain markup.
+                output(match glyph {
+                    Some(glyph) => Cow::Owned(escape_html(glyph)),
+                    None => spelling(variation, true),
+                })?;
+                output(Cow::Borrowed("<rt>"))?;
+                output(render(Variation::Default, false))?;
+                output(Cow::Borrowed("</rt></ruby>"))?;
+            }
+        }
+        self.prepend_space = !self.explicit_spaces;
+        Ok(())
+    }
+}
+
+/// The code points that begin and end a cartouche in UCSUR.
+const CARTOUCHE_START: char = '\u{f1990}';
+const CARTOUCHE_END: char = '\u{f1991}';
+
+/// Renders the words of a cartouche in the given variation. In sitelen pona, these are the
+/// words' glyphs inside a cartouche; otherwise, or if a word has no glyph, it is the name
+/// spelt by the first letter of each word in the default orthography.
+fn render_cartouche(
+    words: &[WordIdentifier],
+    dict_set: &DictionarySet,
+    variation: Variation,
+) -> String {
+    if variation == Variation::SitelenPona {
+        let glyphs = words
+            .iter()
+            .map(|word| dict_set.get_word_variation_opt(*word, variation))
+            .collect::<Option<String>>();
+        if let Some(glyphs) = glyphs {
+            return format!("{}{}{}", CARTOUCHE_START, glyphs, CARTOUCHE_END);
+        }
+    }
+    let letters = words
+        .iter()
+        .filter_map(|word| dict_set.word_str(*word).chars().next())
+        .collect::<String>();
+    capitalize_first(&letters)
+}
+
+/// Escapes the characters that have a special meaning in HTML.
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '&' => escaped.push_str("&amp;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Converts the letters of a word given by a case mask to upper case.
+fn apply_case_mask(word: &str, mask: &[u8]) -> String {
+    let mut cased = String::with_capacity(word.len());
+    for (i, c) in word.chars().enumerate() {
+        if mask.get(i / 8).is_some_and(|byte| byte & 1 << (i % 8) != 0) {
+            cased.extend(c.to_uppercase());
+        } else {
+            cased.push(c);
+        }
+    }
+    cased
+}
+
+/// Converts a word to lower case, returning which of its letters were upper case.
+/// Returns None if a letter does not have a single-character lower case form.
+fn lowercase_with_case(word: &str) -> Option<(String, Vec<bool>)> {
+    let mut lower = String::with_capacity(word.len());
+    let mut case = Vec::new();
+    for c in word.chars() {
+        let mut lower_chars = c.to_lowercase();
+        let lower_char = lower_chars.next()?;
+        if lower_chars.next().is_some() {
+            return None;
+        }
+        lower.push(lower_char);
+        case.push(c != lower_char);
+    }
+    Some((lower, case))
+}
+
+/// Converts the first letter of a word to lower case.
+fn lowercase_first(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_lowercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Converts the first letter of a word to upper case.
+fn capitalize_first(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// How an encoder lays out the bytes of each word.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Each word is a single byte giving its index in the dictionary set.
+    #[default]
+    Compact,
+    /// Each word is a SpeltWord instruction containing its spelling in the default
+    /// orthography, so that the words can be read or searched for in the raw bytes.
+    Debug,
+}
+
+/// Options that change how an encoder interprets its input text.
+#[derive(Debug, Default)]
+struct EncoderOptions {
+    /// If a token is not a word, retry the lookup with apostrophes and accents removed.
+    normalize_diacritics: bool,
+    /// Treat capitalised words at the start of a sentence as dictionary words, and
+    /// capitalised words elsewhere as proper nouns.
+    sentence_case: bool,
+    /// Record the case of every letter of each word, so that any casing round-trips.
+    preserve_case: bool,
+    /// Write an end-of-stream marker when the encoder is finished.
+    end_of_stream: bool,
+    /// If present, numbers written in digits are spelt out in this system.
+    numerals: Option<NumeralSystem>,
+    /// Encode and flush every word as soon as it is known to be complete.
+    flush_per_word: bool,
+    /// Characters other than the ASCII space that separate words.
+    word_separators: Vec<char>,
+    /// How the bytes of each word are laid out.
+    layout: Layout,
+    /// Write every space as a Space instruction, rather than leaving spaces implicit.
+    explicit_spaces: bool,
+    /// Only accept words written in the active variation, not the default orthography.
+    strict_variation: bool,
+    /// If present, the stream begins with a hint that the message is in this variation.
+    variation_hint: Option<Variation>,
+    /// The character that begins and ends a code span.
+    code_delimiter: Option<char>,
+    /// Encode names as cartouches of words, as they are written in sitelen pona.
+    cartouches: bool,
+    /// Reject upper case letters rather than interpreting them.
+    assume_lowercase: bool,
+    /// Encode chat shorthand such as `tp` as the words it stands for.
+    abbreviations: bool,
+    /// Encode text that is not a sequence of words as raw text, rather than failing.
+    raw_text_fallback: bool,
+    /// The code and words of each phrase that may be encoded as a Phrase instruction.
+    /// This is empty if phrases are not encoded.
+    phrases: Vec<(u8, Vec<WordIdentifier>)>,
 }
 
+/// Once this many characters of text without spaces are buffered, the text is encoded
+/// as soon as it can be, to bound the work of splitting it into words.
+const SPLIT_TOKEN_CHARS: usize = 16;
+
+/// Once this many characters of text without spaces are buffered, the text is encoded
+/// even if it cannot be, which may split a word.
+const MAX_TOKEN_CHARS: usize = 256;
+
 /// Encodes text into the toki pona encoding.
 #[derive(Debug)]
-pub struct Encoder<T: io::Write> {
+pub struct Encoder<'d, T: io::Write> {
     writer: T,
+    /// The words that can be encoded.
+    /// The decoder must be constructed with the same dictionary set.
+    dict_set: &'d DictionarySet<'d>,
     state: EncodingState,
+    options: EncoderOptions,
     /// We may have some text that is not encoded yet, but that might
     /// be more efficiently encoded later. This unencoded text is stored here.
     /// Stored as a Vec<char> for convenience; UTF-8 is likely unnecessary.
     unencoded: Vec<char>,
+    /// The number of bytes of input text written so far.
+    offset: usize,
+    /// The offset of the start of the unencoded text in the input.
+    unencoded_offset: usize,
+    /// True if no word has been written since the start of the text or the last
+    /// sentence-ending punctuation mark.
+    sentence_start: bool,
+    /// Words that have been encoded but not yet written, because they may begin a phrase.
+    held: Vec<WordIdentifier>,
+    /// True once any instruction has been written.
+    started: bool,
+    /// The bytes of a character that was split between two calls to `io::Write::write`.
+    partial_utf8: Vec<u8>,
+    /// The text of the code span being written, starting with its delimiter, and whether
+    /// it was preceded by a space.
+    code_span: Option<(String, bool)>,
 }
 
-impl<T: io::Write> Encoder<T> {
+impl<'d, T: io::Write> Encoder<'d, T> {
     /// Encodes input text and writes it to the given writer.
     pub fn new(writer: T) -> Self {
+        Self::with_dict_set(writer, &DICT_SET)
+    }
+
+    /// Encodes input text using the words in the given dictionary set.
+    /// The output must be decoded with a decoder constructed with the same set.
+    pub fn with_dict_set(writer: T, dict_set: &'d DictionarySet<'d>) -> Self {
         Self {
             writer,
+            dict_set,
             state: EncodingState::default(),
+            options: EncoderOptions::default(),
             unencoded: Vec::new(),
+            offset: 0,
+            unencoded_offset: 0,
+            sentence_start: true,
+            held: Vec::new(),
+            started: false,
+            partial_utf8: Vec::new(),
+            code_span: None,
         }
     }
 
+    /// Encodes input text written in the given variation, without writing a SetVariation
+    /// instruction. Words are encoded the same way in every variation, so this writes the
+    /// fewest bytes when the decoder is known to use the same variation, as with
+    /// `Decoder::with_variation`; other decoders render the words in their own variation.
+    pub fn with_variation(writer: T, variation: Variation) -> Self {
+        Self::with_variation_and_dict_set(writer, variation, &DICT_SET)
+    }
+
+    /// Encodes input text written in the given variation, as for `with_variation`,
+    /// using the words in the given dictionary set.
+    pub fn with_variation_and_dict_set(
+        writer: T,
+        variation: Variation,
+        dict_set: &'d DictionarySet<'d>,
+    ) -> Self {
+        let mut encoder = Self::with_dict_set(writer, dict_set);
+        encoder.state.variation = variation;
+        encoder
+    }
+
+    /// If enabled, tokens that are not words are looked up again with apostrophes and
+    /// accents removed, so that `a'` and `à` encode as `a`. This is lossy: the decoded
+    /// text contains the base word only. Tokens that are words as written, such as
+    /// glyphs in a variation that uses diacritics, are never altered.
+    /// Disabled by default, in which case such tokens fail to encode.
+    pub fn normalize_diacritics(&mut self, enabled: bool) -> &mut Self {
+        self.options.normalize_diacritics = enabled;
+        self
+    }
+
+    /// If enabled, follows the convention of capitalising the first word of a sentence.
+    /// A capitalised word at the start of the text or after `.`, `?` or `!` is encoded as
+    /// the lowercase word and a capitalisation marker. Any other capitalised word, or one
+    /// that is not in the dictionary set, is a proper noun and is encoded as raw text.
+    /// Disabled by default, in which case capitalised words fail to encode.
+    pub fn sentence_case(&mut self, enabled: bool) -> &mut Self {
+        self.options.sentence_case = enabled;
+        self
+    }
+
+    /// If enabled, words are looked up in lower case, and the case of every letter is
+    /// recorded so that text such as `tOkI` decodes exactly as written. Capitalised words
+    /// cost one extra byte, and words with any other casing cost a few bytes more.
+    /// Sentence case, if also enabled, takes precedence for words that start with a
+    /// capital letter. Disabled by default, in which case words with upper case letters
+    /// fail to encode.
+    pub fn preserve_case(&mut self, enabled: bool) -> &mut Self {
+        self.options.preserve_case = enabled;
+        self
+    }
+
+    /// If enabled, shorthand used in chat that is not itself a word, such as `tp` for
+    /// `toki pona`, is encoded as a two-byte Abbreviation instruction, listed in
+    /// `abbreviations::ABBREVIATIONS`. It decodes to the words it stands for, or back to
+    /// the shorthand with `Decoder::contract_abbreviations`.
+    /// Disabled by default, in which case shorthand fails to encode.
+    pub fn abbreviations(&mut self, enabled: bool) -> &mut Self {
+        self.options.abbreviations = enabled;
+        self
+    }
+
+    /// If enabled, the input is known to be lower case, as in a preprocessed corpus, so text
+    /// is looked up in the dictionary set as it is, without the work of `sentence_case` and
+    /// `preserve_case`. Any upper case letter fails to encode with `EncodeError::UpperCase`,
+    /// even if `raw_text_fallback` is enabled. Disabled by default.
+    pub fn assume_lowercase(&mut self, enabled: bool) -> &mut Self {
+        self.options.assume_lowercase = enabled;
+        self
+    }
+
+    /// If enabled, text that cannot be split into words, such as a name or a URL, is encoded
+    /// as raw text, which costs two bytes more than the text itself. Text that is run
+    /// together without spaces is still split into words where possible, so that
+    /// `#tokipona` encodes as `#` followed by the words `toki` and `pona`.
+    /// Disabled by default, in which case such text fails to encode.
+    pub fn raw_text_fallback(&mut self, enabled: bool) -> &mut Self {
+        self.options.raw_text_fallback = enabled;
+        self
+    }
+
+    /// If a numeral system is given, tokens made of Arabic digits such as `7` are encoded
+    /// as the toki pona words for that number, such as `luka tu`, which are each spaced
+    /// like any other word. The decoded text contains the words, not the digits.
+    /// Disabled by default, in which case digits fail to encode.
+    pub fn numerals(&mut self, system: Option<NumeralSystem>) -> &mut Self {
+        self.options.numerals = system;
+        self
+    }
+
+    /// If enabled, each word is encoded and the writer is flushed as soon as the word is
+    /// complete, so that the bytes written so far always decode to every word so far.
+    /// This is useful for live encoding. The end of each call to `write_text` or
+    /// `write_chars` ends a word, so words must not be split across calls; a call that
+    /// does not start with a space is attached to the previous word, as usual.
+    /// Disabled by default, in which case the last word is buffered until more text
+    /// arrives, so that chunk boundaries are not token boundaries.
+    pub fn flush_per_word(&mut self, enabled: bool) -> &mut Self {
+        self.options.flush_per_word = enabled;
+        self
+    }
+
+    /// If enabled, every space between words is written as a Space instruction, and words
+    /// that are attached together simply have no Space between them, so that the bytes
+    /// mirror the structure of the text. The stream begins with an ExplicitSpaces
+    /// instruction that tells the decoder which model is used, and costs a byte per space.
+    /// Phrases are not encoded in this mode. This must be set before any text is written.
+    /// Disabled by default, in which case spaces before words are implicit.
+    pub fn explicit_spaces(&mut self, enabled: bool) -> &mut Self {
+        self.options.explicit_spaces = enabled;
+        self
+    }
+
+    /// If enabled, words must be written in the active variation, rather than in either it
+    /// or the default orthography, so a word written in the wrong script is an unknown
+    /// word. This checks that a document is written purely in one orthography.
+    /// Disabled by default.
+    pub fn strict_variation(&mut self, enabled: bool) -> &mut Self {
+        self.options.strict_variation = enabled;
+        self
+    }
+
+    /// Begins the stream with a hint that the message is written in the given variation,
+    /// so that the message describes its own script, and makes it the active variation
+    /// without a SetVariation instruction. A decoder renders the message's words in this
+    /// variation, and reports it from `Decoder::variation_hint`. The hint costs two bytes.
+    /// This must be set before any text is written. By default, no hint is written.
+    pub fn variation_hint(&mut self, variation: Variation) -> &mut Self {
+        self.options.variation_hint = Some(variation);
+        self.state.variation = variation;
+        self
+    }
+
+    /// If present, text between a pair of this character, such as `` `HashMap` ``, is a
+    /// code span: it is written as it is, spaces and all, in a single CodeSpan instruction,
+    /// rather than being looked up in the dictionary set. The delimiters are kept. A code
+    /// span may be at most 255 bytes long. By default, there are no code spans.
+    pub fn code_delimiter(&mut self, delimiter: Option<char>) -> &mut Self {
+        self.options.code_delimiter = delimiter;
+        self
+    }
+
+    /// If enabled, a name is encoded as a cartouche, the way names are written in sitelen
+    /// pona: a sequence of words that each stand for their first letter. Glyphs written
+    /// between the UCSUR cartouche code points U+F1990 and U+F1991 are encoded as a
+    /// cartouche of those words. With `sentence_case`, a proper noun spelt
+    /// with toki pona letters only, such as `Sonja`, is encoded as a cartouche of a word
+    /// for each letter rather than as raw text. A cartouche decodes to the glyphs in their
+    /// cartouche in sitelen pona, and to the name spelt out in any other variation, so
+    /// that a name written in either form round-trips. Disabled by default.
+    pub fn cartouches(&mut self, enabled: bool) -> &mut Self {
+        self.options.cartouches = enabled;
+        self
+    }
+
+    /// Chooses how the bytes of each word are laid out. The debug layout is several times
+    /// larger than the compact layout, but any decoder reads either layout without being
+    /// told which was used, since each instruction describes itself.
+    /// The compact layout is used by default.
+    pub fn layout(&mut self, layout: Layout) -> &mut Self {
+        self.options.layout = layout;
+        self
+    }
+
+    /// If enabled, each common phrase listed in `phrases::PHRASES`, such as `tenpo ni la`,
+    /// is encoded as a single Phrase instruction of two bytes, rather than a byte per word.
+    /// Words that may begin a phrase are held back until the phrase is complete or
+    /// broken, or the encoder is finished, so are written later than other words.
+    /// Disabled by default.
+    pub fn phrases(&mut self, enabled: bool) -> &mut Self {
+        self.options.phrases = if enabled {
+            (0..=u8::MAX)
+                .zip(PHRASES)
+                .filter_map(|(code, _)| Some((code, phrases::words(self.dict_set, code)?)))
+                .collect()
+        } else {
+            Vec::new()
+        };
+        self
+    }
+
+    /// Treats each of these characters as a space between words, in addition to the ASCII
+    /// space, such as the middle dot `·` that separates words in some sitelen pona text.
+    /// The decoded text separates words with ASCII spaces. By default, only the ASCII space
+    /// separates words, and other separators fail to encode.
+    pub fn word_separators(&mut self, separators: &[char]) -> &mut Self {
+        self.options.word_separators = separators.to_vec();
+        self
+    }
+
+    /// If enabled, an EndOfStream instruction is written when the encoder is finished or
+    /// dropped, so that the decoder can tell where this message ends and another begins.
+    /// Disabled by default, which saves a byte for streams holding a single message.
+    pub fn end_of_stream(&mut self, enabled: bool) -> &mut Self {
+        self.options.end_of_stream = enabled;
+        self
+    }
+
     /// Process the given text and output the generated instructions to the writer.
-    pub fn write_text(&mut self, text: &str) {
-        for c in text.chars() {
-            self.write_character(c);
+    ///
+    /// Text may be written in any number of chunks. Chunk boundaries are not token
+    /// boundaries: the output is the same as if all of the chunks were written at once.
+    /// For example, writing `"toki"` then `"pona"` encodes `"tokipona"`, so callers must
+    /// include the space between chunks themselves if the words are meant to be separate.
+    pub fn write_text(&mut self, text: &str) -> Result<(), EncodeError> {
+        self.write_chars(text.chars())
+    }
+
+    /// Process the characters produced by the given iterator, such as a stream of
+    /// characters that has not been collected into a string.
+    pub fn write_chars<I: Iterator<Item = char>>(&mut self, chars: I) -> Result<(), EncodeError> {
+        for c in chars {
+            self.write_character(c)?;
+        }
+        if self.options.flush_per_word {
+            // The end of the text is the end of a word.
+            self.encode()?;
+            self.writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Changes the variation that subsequent words are written in.
+    /// The decoder will render subsequent words in this variation too.
+    pub fn set_variation(&mut self, variation: Variation) -> Result<(), EncodeError> {
+        if variation == self.state.variation {
+            return Ok(());
         }
+        // Any buffered text was written in the previous variation.
+        self.encode()?;
+        self.state.variation = variation;
+        self.write(Instruction::SetVariation { variation })
     }
 
-    pub fn write_character(&mut self, c: char) {
-        if c == ' ' || self.unencoded.len() >= 16 {
-            self.encode();
+    /// Writes words that have already been split into tokens, such as by a better
+    /// tokenizer than the encoder's own. Each token must be a single word, and is paired with
+    /// true if it is attached to the token before it rather than separated by a space.
+    /// Any buffered text is encoded first.
+    pub fn write_pretokenized(&mut self, tokens: &[(&str, bool)]) -> Result<(), EncodeError> {
+        self.encode()?;
+        for (token, attached) in tokens {
+            let word = if self.options.strict_variation {
+                self.dict_set
+                    .get_identifier_strict(token, self.state.variation)
+            } else {
+                self.dict_set
+                    .get_identifier_variation(token, self.state.variation)
+            }
+            .ok_or_else(|| EncodeError::UnknownWord((*token).to_owned()))?;
+            if self.state.prepend_space {
+                if self.options.explicit_spaces {
+                    if !attached {
+                        self.write(Instruction::Space)?;
+                    }
+                } else if *attached {
+                    self.write(Instruction::AttachToPrevious)?;
+                }
+            }
+            self.write(Instruction::TokiPonaWord { word })?;
+            self.state.prepend_space = true;
+            self.sentence_start = false;
+        }
+        Ok(())
+    }
+
+    pub fn write_character(&mut self, c: char) -> Result<(), EncodeError> {
+        let len = c.len_utf8();
+        self.offset += len;
+        if let Some((span, _)) = &mut self.code_span {
+            span.push(c);
+            if Some(c) == self.options.code_delimiter {
+                self.write_code_span()?;
+            }
+            return Ok(());
+        }
+        if c != ' ' && Some(c) == self.options.code_delimiter {
+            // A code span after a space is spaced like a word, and is otherwise attached.
+            let spaced = self.unencoded == [' '];
+            if spaced {
+                self.unencoded.clear();
+            } else {
+                self.encode()?;
+            }
+            self.code_span = Some((c.to_string(), spaced));
+            return Ok(());
+        }
+        let c = if self.options.word_separators.contains(&c) {
+            ' '
+        } else {
+            c
+        };
+        if c == ' '
+            || ends_punctuation(&self.unencoded, c)
+            || (self.unencoded.len() >= SPLIT_TOKEN_CHARS
+                && !continues_grapheme(&self.unencoded, c)
+                && (self.unencoded.len() >= MAX_TOKEN_CHARS || self.unencoded_is_complete()))
+            || (self.state.variation.is_unspaced()
+                && !is_clinging_punctuation(c)
+                && !continues_grapheme(&self.unencoded, c)
+                && self.unencoded_is_complete())
+        {
+            self.encode()?;
+        }
+        if self.unencoded.is_empty() {
+            self.unencoded_offset = self.offset - len;
         }
         self.unencoded.push(c);
+        Ok(())
+    }
+
+    /// Writes the code span that has been read, attaching it to the previous word if it was
+    /// not preceded by a space.
+    fn write_code_span(&mut self) -> Result<(), EncodeError> {
+        let Some((text, spaced)) = self.code_span.take() else {
+            return Ok(());
+        };
+        if self.state.prepend_space {
+            if self.options.explicit_spaces {
+                if spaced {
+                    self.write(Instruction::Space)?;
+                }
+            } else if !spaced {
+                self.write(Instruction::AttachToPrevious)?;
+            }
+        }
+        self.write(Instruction::CodeSpan { text })?;
+        self.state.prepend_space = true;
+        self.sentence_start = false;
+        Ok(())
+    }
+
+    /// Returns true if the unencoded text could be encoded by itself, so that a long run of
+    /// words without spaces such as `tokiponatokipona` can be split here without splitting
+    /// a word. The rest of the run is attached to it when decoding.
+    fn unencoded_is_complete(&self) -> bool {
+        let text = self.unencoded.iter().collect::<String>();
+        // Text that falls back to raw text may still be the start of a word.
+        self.tokenize(text.strip_prefix(' ').unwrap_or(&text), false)
+            .is_ok()
+    }
+
+    /// Encodes the text written so far and flushes the writer, so that the bytes written so
+    /// far decode to all of that text. Words held back because they may begin a phrase are
+    /// written too, and a code span that is not yet closed stays buffered. The encoder
+    /// stays usable, and later text continues the same stream; only `finish` ends it.
+    /// As with `flush_per_word`, the end of the flushed text ends a word, and text written
+    /// after it that does not start with a space is attached to it.
+    /// `io::Write::flush` does the same, but keeps the first bytes of a character that was
+    /// split between calls to `write` until the rest arrive.
+    pub fn flush(&mut self) -> Result<(), EncodeError> {
+        self.encode()?;
+        self.write_held(true)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Encodes any remaining unencoded text, and returns an error if it could not be encoded.
+    /// Dropping the encoder also encodes the remaining text, but ignores any errors.
+    pub fn finish(mut self) -> Result<(), EncodeError> {
+        self.end()
+    }
+
+    /// Encodes the remaining text, and writes the end-of-stream marker if enabled.
+    fn end(&mut self) -> Result<(), EncodeError> {
+        // Text that ends partway through a character is an error, but the text before it is
+        // still encoded here, rather than being left for `drop` to write after the error.
+        let partial_utf8 = !self.partial_utf8.is_empty();
+        self.partial_utf8.clear();
+        // A code span that is never closed is still written as it is.
+        self.write_code_span()?;
+        self.encode()?;
+        self.write_held(true)?;
+        if self.options.end_of_stream {
+            // Only write the marker once, even though `finish` is followed by `drop`.
+            self.options.end_of_stream = false;
+            self.write(Instruction::EndOfStream)?;
+        }
+        if partial_utf8 {
+            return Err(EncodeError::InvalidUtf8);
+        }
+        Ok(())
     }
 
     /// Encode what remains in the unencoded text.
-    /// After this method, the unencoded text will be empty.
-    fn encode(&mut self) {
+    /// After this method, the unencoded text will be empty, even if encoding failed.
+    fn encode(&mut self) -> Result<(), EncodeError> {
+        if self.unencoded.is_empty() {
+            return Ok(());
+        }
+
         // Check if the buffer represents a word.
         let manually_attach_to_previous;
         // If the buffer represents a toki pona word, this string is that word.
@@ -91,98 +1416,1746 @@ impl<T: io::Write> Encoder<T> {
             chars.collect::<String>()
         };
 
-        if let Some(word) = DICT_SET.get_identifier_variation(&toki_pona_word, self.state.variation)
-        {
-            // This was a toki pona word.
-
-            // The previous word was a toki pona word, but there was no space between this word and the previous.
-            if manually_attach_to_previous {
+        let instructions = match self.tokenize(&toki_pona_word, self.options.raw_text_fallback) {
+            Ok(instructions) => instructions,
+            Err(err) => {
+                self.unencoded.clear();
+                return Err(err);
+            }
+        };
+        let explicit = self.options.explicit_spaces;
+        let mut attach = manually_attach_to_previous;
+        // With explicit spaces, this is true if a space must be written before the next word.
+        let mut space = self.state.prepend_space && !manually_attach_to_previous;
+        for (instruction, spaced) in instructions {
+            // Either the previous word was a toki pona word but there was no space between
+            // this word and the previous, or this word was run together with the word before
+            // it in the same buffer. Punctuation is always attached to the previous word.
+            let punctuation = matches!(
+                instruction,
+                Instruction::Punctuation { .. }
+                    | Instruction::PunctuationRun { .. }
+                    | Instruction::GroupClose
+            );
+            if explicit {
+                if space && !punctuation {
+                    self.write(Instruction::Space)?;
+                }
+            } else if attach && !punctuation {
                 // We expected a space character, but one was not given.
                 // We must emit an instruction to attach this word to the previous
                 // word when decoding.
-                self.write(Instruction::AttachToPrevious);
+                self.write(Instruction::AttachToPrevious)?;
             }
+            // A capitalisation marker belongs to the word after it, so that word is not
+            // attached to anything, nor spaced from the marker. An opening bracket is
+            // always attached to the word after it.
+            let marker = matches!(
+                instruction,
+                Instruction::Capitalize | Instruction::CaseMask { .. } | Instruction::GroupOpen
+            );
+            attach = !spaced && !marker;
+            space = spaced && !marker;
 
             // io::Write the instruction to the writer.
-            self.write(Instruction::TokiPonaWord { word });
-            // If the next word is a toki pona word, we will expect to put a space before it.
-            self.state.prepend_space = true;
-        } else {
-            panic!(
-                "encoding failed for character sequence [{}]",
-                toki_pona_word
-            );
+            self.write(instruction)?;
         }
+        // If the next word is a toki pona word, we will expect to put a space before it.
+        self.state.prepend_space = true;
+        let (leading, core, trailing) = strip_punctuation(&toki_pona_word);
+        let punctuation = if core.is_empty() { leading } else { trailing };
+        self.sentence_start = punctuation.contains(['.', '?', '!']);
 
         self.unencoded.clear();
+        if self.options.flush_per_word {
+            self.write_held(true)?;
+            self.writer.flush()?;
+        }
+        Ok(())
     }
 
-    fn write(&mut self, instruction: Instruction) {
-        self.writer
-            .write_all(&instruction.encode())
-            .expect("writing failed");
-    }
-}
-
-impl<T: io::Write> Drop for Encoder<T> {
-    fn drop(&mut self) {
-        // Process the remaining unencoded text.
-        self.encode();
+    /// Converts a token containing no spaces into a sequence of instructions that
+    /// print words, emoji or punctuation, which should be attached together when decoding.
+    /// Each instruction is paired with true if it should instead be spaced from the
+    /// instruction after it, as for the words of a spelt out number.
+    /// Punctuation at the start of the token is printed as raw text, punctuation at the end
+    /// is attached to the last word, and the word between is looked up on its own.
+    /// If `fallback` is true, text between the punctuation that is not a sequence of words
+    /// is printed as raw text rather than returning an error.
+    fn tokenize(
+        &self,
+        token: &str,
+        fallback: bool,
+    ) -> Result<Vec<(Instruction, bool)>, EncodeError> {
+        let (leading, core, trailing) = strip_punctuation(token);
+        let mut instructions = Vec::new();
+        if !leading.is_empty() && !core.is_empty() && leading.chars().all(|c| c == '(') {
+            // Each bracket attaches to the next, and the last to the first word.
+            instructions.extend((0..leading.len()).map(|_| (Instruction::GroupOpen, false)));
+        } else if !leading.is_empty() {
+            instructions.push((
+                Instruction::RawText {
+                    text: leading.to_owned(),
+                },
+                false,
+            ));
+        }
+        // With numerals, digits run together with words, as in `3mute`, are a number of
+        // their own, spaced from the words beside it.
+        let runs = if self.options.numerals.is_some() {
+            split_digit_runs(core)
+        } else {
+            vec![core]
+        };
+        for (i, run) in runs.iter().enumerate() {
+            match self.spell_number(run) {
+                // Spelt out numbers are several words, each with a space before it.
+                Some(words) => instructions.extend(words?.into_iter().map(|word| (word, true))),
+                None => {
+                    match self.tokenize_words(run) {
+                        Ok(words) => {
+                            instructions.extend(words.into_iter().map(|word| (word, false)))
+                        }
+                        Err(EncodeError::UnknownWord(_)) if fallback && !run.is_empty() => {
+                            instructions.push((
+                                Instruction::RawText {
+                                    text: (*run).to_owned(),
+                                },
+                                false,
+                            ))
+                        }
+                        Err(err) => return Err(err),
+                    }
+                    if i + 1 < runs.len() {
+                        if let Some((_, spaced)) = instructions.last_mut() {
+                            *spaced = true;
+                        }
+                    }
+                }
+            }
+        }
+        if !trailing.is_empty() {
+            // Marks that have their own instruction take a byte each, which is never
+            // longer than a run.
+            let marks = trailing
+                .chars()
+                .map(|c| match c {
+                    ')' => Ok(Instruction::GroupClose),
+                    _ => Punctuation::try_from(c).map(|mark| Instruction::Punctuation { mark }),
+                })
+                .collect::<Result<Vec<_>, _>>();
+            match marks {
+                Ok(marks) => instructions.extend(marks.into_iter().map(|mark| (mark, false))),
+                Err(()) => instructions.push((
+                    Instruction::PunctuationRun {
+                        text: trailing.to_owned(),
+                    },
+                    false,
+                )),
+            }
+        }
+        if instructions.is_empty() {
+            return Err(EncodeError::UnknownWord(token.to_owned()));
+        }
+        Ok(instructions)
     }
-}
 
-/// Decodes text from the toki pona encoding into a io::Writer.
-#[derive(Debug)]
-pub struct Decoder<T> {
+    /// Converts text containing no spaces or punctuation into a sequence of instructions
+    /// that print words or emoji.
+    fn tokenize_words(&self, core: &str) -> Result<Vec<Instruction>, EncodeError> {
+        if self.options.assume_lowercase && core.chars().any(char::is_uppercase) {
+            return Err(EncodeError::UpperCase(core.to_owned()));
+        }
+        let mut instructions = Vec::new();
+        for (i, piece) in split_emoji(core).into_iter().enumerate() {
+            match piece {
+                Piece::Text(text)
+                    if self.options.cartouches && text.starts_with(CARTOUCHE_START) =>
+                {
+                    let words = text
+                        .strip_prefix(CARTOUCHE_START)
+                        .and_then(|text| text.strip_suffix(CARTOUCHE_END))
+                        .and_then(|glyphs| self.segment(glyphs))
+                        .ok_or_else(|| EncodeError::UnknownWord(text.to_owned()))?;
+                    instructions.push(Instruction::Cartouche { words });
+                }
+                Piece::Text(text)
+                    if self.options.sentence_case && text.starts_with(char::is_uppercase) =>
+                {
+                    let words = if i == 0 && self.sentence_start {
+                        self.segment(&lowercase_first(text))
+                    } else {
+                        None
+                    };
+                    match words {
+                        Some(words) => {
+                            instructions.push(Instruction::Capitalize);
+                            instructions.extend(
+                                words
+                                    .into_iter()
+                                    .map(|word| Instruction::TokiPonaWord { word }),
+                            );
+                        }
+                        // This is a proper noun.
+                        None => instructions.push(match self.spell_name(text) {
+                            Some(words) => Instruction::Cartouche { words },
+                            None => Instruction::RawText {
+                                text: text.to_owned(),
+                            },
+                        }),
+                    }
+                }
+                Piece::Text(text)
+                    if self.options.preserve_case && text.chars().any(char::is_uppercase) =>
+                {
+                    instructions.extend(self.tokenize_cased(text)?);
+                }
+                Piece::Text(text) => {
+                    let words = self.segment(text).or_else(|| {
+                        if self.options.normalize_diacritics {
+                            self.segment(&strip_diacritics(text))
+                        } else {
+                            None
+                        }
+                    });
+                    match words {
+                        Some(words) => instructions.extend(
+                            words
+                                .into_iter()
+                                .map(|word| Instruction::TokiPonaWord { word }),
+                        ),
+                        None => {
+                            let code = self
+                                .abbreviation(text)
+                                .ok_or_else(|| EncodeError::UnknownWord(text.to_owned()))?;
+                            instructions.push(Instruction::Abbreviation { code });
+                        }
+                    }
+                }
+                Piece::Emoji(emoji) => instructions.push(Instruction::RawText {
+                    text: emoji.to_owned(),
+                }),
+            }
+        }
+        Ok(instructions)
+    }
+
+    /// If cartouches are enabled and this name is a capital letter followed by lower case
+    /// letters of toki pona, returns a word for each letter, which begins with that letter.
+    /// Otherwise, returns None.
+    fn spell_name(&self, name: &str) -> Option<Vec<WordIdentifier>> {
+        if !self.options.cartouches || capitalize_first(&name.to_lowercase()) != name {
+            return None;
+        }
+        name.to_lowercase()
+            .chars()
+            .map(|letter| {
+                if !"aeijklmnopstuw".contains(letter) {
+                    return None;
+                }
+                // The first word with this letter stands for it.
+                self.dict_set
+                    .words()
+                    .find(|word| self.dict_set.word_str(*word).starts_with(letter))
+            })
+            .collect()
+    }
+
+    /// If abbreviations are enabled and this text is one whose words are all in the
+    /// dictionary set, returns its code. Otherwise, returns None.
+    fn abbreviation(&self, text: &str) -> Option<u8> {
+        if !self.options.abbreviations {
+            return None;
+        }
+        let code = abbreviations::code(text)?;
+        abbreviations::words(self.dict_set, code).map(|_| code)
+    }
+
+    /// Splits text into words written in the active variation, falling back to the
+    /// default orthography unless the `strict_variation` option is enabled.
+    fn segment(&self, text: &str) -> Option<Vec<WordIdentifier>> {
+        if self.options.strict_variation {
+            self.dict_set.segment_strict(text, self.state.variation)
+        } else {
+            self.dict_set.segment(text, self.state.variation)
+        }
+    }
+
+    /// If spelling out numbers is enabled and the token is a number written in digits,
+    /// returns the words for that number. Otherwise, returns None.
+    fn spell_number(&self, token: &str) -> Option<Result<Vec<Instruction>, EncodeError>> {
+        let system = self.options.numerals?;
+        if token.is_empty() || !token.bytes().all(|byte| byte.is_ascii_digit()) {
+            return None;
+        }
+        let words = token
+            .parse()
+            .ok()
+            .and_then(|number| system.spell(number))
+            .ok_or_else(|| EncodeError::NumberTooLarge(token.to_owned()));
+        Some(words.and_then(|words| {
+            words
+                .into_iter()
+                .map(|word| {
+                    let word = self
+                        .dict_set
+                        .get_identifier(word)
+                        .ok_or_else(|| EncodeError::UnknownWord(word.to_owned()))?;
+                    Ok(Instruction::TokiPonaWord { word })
+                })
+                .collect()
+        }))
+    }
+
+    /// Converts text containing upper case letters into a sequence of words, each preceded
+    /// by an instruction recording its case if it is not entirely lower case.
+    fn tokenize_cased(&self, text: &str) -> Result<Vec<Instruction>, EncodeError> {
+        let variation = self.state.variation;
+        let (lower, case) =
+            lowercase_with_case(text).ok_or_else(|| EncodeError::UnknownWord(text.to_owned()))?;
+        let words = self
+            .segment(&lower)
+            .ok_or_else(|| EncodeError::UnknownWord(text.to_owned()))?;
+
+        let mut instructions = Vec::new();
+        let mut rest = lower.as_str();
+        let mut case = case.as_slice();
+        for word in words {
+            // The word may have been found in this variation or the default orthography.
+            let spelling = [variation, Variation::Default]
+                .into_iter()
+                .map(|variation| self.dict_set.get_word_variation(word, variation))
+                .find(|spelling| rest.starts_with(spelling))
+                .expect("segmented word should prefix the text");
+            let (word_case, rest_case) = case.split_at(spelling.chars().count());
+            if word_case.iter().skip(1).any(|upper| *upper) {
+                let mut mask = Vec::new();
+                for (i, _) in word_case.iter().enumerate().filter(|(_, upper)| **upper) {
+                    mask.resize(mask.len().max(i / 8 + 1), 0);
+                    mask[i / 8] |= 1 << (i % 8);
+                }
+                instructions.push(Instruction::CaseMask { mask });
+            } else if word_case.first() == Some(&true) {
+                instructions.push(Instruction::Capitalize);
+            }
+            instructions.push(Instruction::TokiPonaWord { word });
+            rest = &rest[spelling.len()..];
+            case = rest_case;
+        }
+        Ok(instructions)
+    }
+
+    fn write(&mut self, instruction: Instruction) -> Result<(), EncodeError> {
+        if !self.started {
+            self.started = true;
+            if let Some(variation) = self.options.variation_hint {
+                self.write_now(Instruction::VariationHint { variation })?;
+            }
+            if self.options.explicit_spaces {
+                self.write_now(Instruction::ExplicitSpaces)?;
+            }
+        }
+        if !self.options.phrases.is_empty() && !self.options.explicit_spaces {
+            if let Instruction::TokiPonaWord { word } = instruction {
+                self.held.push(word);
+                return self.write_held(false);
+            }
+            // Any other instruction breaks a phrase.
+            self.write_held(true)?;
+        }
+        self.write_now(instruction)
+    }
+
+    /// Writes the held words, replacing the longest phrase at each point with a Phrase
+    /// instruction. Unless `all` is true, words that may still begin a phrase stay held.
+    fn write_held(&mut self, all: bool) -> Result<(), EncodeError> {
+        while !self.held.is_empty() {
+            let held = &self.held;
+            let phrases = &self.options.phrases;
+            if !all
+                && phrases
+                    .iter()
+                    .any(|(_, words)| words.len() > held.len() && words.starts_with(held))
+            {
+                break;
+            }
+            let (instruction, len) = match phrases
+                .iter()
+                .filter(|(_, words)| held.starts_with(words))
+                .max_by_key(|(_, words)| words.len())
+            {
+                Some((code, words)) => (Instruction::Phrase { code: *code }, words.len()),
+                None => (Instruction::TokiPonaWord { word: held[0] }, 1),
+            };
+            self.held.drain(..len);
+            self.write_now(instruction)?;
+        }
+        Ok(())
+    }
+
+    fn write_now(&mut self, instruction: Instruction) -> Result<(), EncodeError> {
+        let bytes = instruction.encode(self.dict_set, self.options.layout)?;
+        self.writer.write_all(&bytes)?;
+        Ok(())
+    }
+}
+
+/// Encodes UTF-8 text written as bytes, so that text can be copied into an encoder with
+/// `io::copy`. A character may be split between two calls to `write`, in which case its
+/// first bytes are kept until the rest arrive. Bytes that are not valid UTF-8 are an error
+/// of kind `InvalidData`, as are other encoding errors; `Io` errors are returned as they are.
+/// Flushing encodes the text written so far, as the encoder's own `flush` does.
+impl<T: io::Write> io::Write for Encoder<'_, T> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut bytes = std::mem::take(&mut self.partial_utf8);
+        bytes.extend_from_slice(buf);
+        let (text, rest) = match std::str::from_utf8(&bytes) {
+            Ok(text) => (text, &[][..]),
+            // The bytes end partway through a character.
+            Err(err) if err.error_len().is_none() => {
+                let (valid, rest) = bytes.split_at(err.valid_up_to());
+                (std::str::from_utf8(valid).unwrap(), rest)
+            }
+            Err(_) => return Err(EncodeError::InvalidUtf8.into()),
+        };
+        self.write_text(text)?;
+        self.partial_utf8 = rest.to_vec();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Encoder::flush(self)?;
+        Ok(())
+    }
+}
+
+impl<T: io::Write> Drop for Encoder<'_, T> {
+    fn drop(&mut self) {
+        // Process the remaining unencoded text.
+        // Errors cannot be reported here; use `finish` to observe them.
+        let _ = self.end();
+    }
+}
+
+/// Splits text into runs of ASCII digits and runs of other characters, such as `3` and
+/// `mute` for `3mute`. Empty text is a single empty run.
+fn split_digit_runs(text: &str) -> Vec<&str> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    for (i, pair) in text.as_bytes().windows(2).enumerate() {
+        if pair[0].is_ascii_digit() != pair[1].is_ascii_digit() {
+            runs.push(&text[start..i + 1]);
+            start = i + 1;
+        }
+    }
+    runs.push(&text[start..]);
+    runs
+}
+
+/// Removes apostrophes and accents from Latin letters, such as turning `a'` into `a`.
+fn strip_diacritics(text: &str) -> String {
+    text.chars()
+        .filter_map(|c| {
+            Some(match c {
+                // Apostrophes, and combining diacritical marks.
+                '\'' | '\u{2019}' | '\u{02bc}' | '\u{0300}'..='\u{036f}' => return None,
+                'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' => 'a',
+                'è' | 'é' | 'ê' | 'ë' | 'ē' => 'e',
+                'ì' | 'í' | 'î' | 'ï' | 'ī' => 'i',
+                'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ō' => 'o',
+                'ù' | 'ú' | 'û' | 'ü' | 'ū' => 'u',
+                'ñ' => 'n',
+                _ => c,
+            })
+        })
+        .collect()
+}
+
+/// The number of bytes of text that `Decoder::read_bytes` collects before writing them.
+const DECODE_BATCH_SIZE: usize = 4096;
+
+/// Decodes text from the toki pona encoding into a io::Writer.
+#[derive(Debug)]
+pub struct Decoder<'d, T> {
     writer: T,
+    /// The words that can be decoded.
+    /// This must be the same dictionary set that the encoder used.
+    dict_set: &'d DictionarySet<'d>,
     state: EncodingState,
+    /// The bytes of an instruction that has not been completely read yet.
+    pending: Vec<u8>,
+    /// Which variation words are rendered in.
+    rendering: Rendering,
+    /// The number of bytes of text written so far.
+    output_len: usize,
+    /// The largest number of bytes of text that may be written.
+    max_output_len: usize,
+    /// The number of bytes read so far.
+    position: usize,
+    /// If this is true, bytes are skipped until the next word; see `resync`.
+    resyncing: bool,
+    /// What to do with bytes that do not begin any known instruction.
+    unknown_bytes: UnknownBytes,
+    /// True if an AttachToPrevious instruction has been read, but no text since.
+    dangling_attach: bool,
+    /// The variation given by the last VariationHint instruction read.
+    variation_hint: Option<Variation>,
+    /// If present, groups must be balanced and nested at most this deep.
+    max_group_depth: Option<usize>,
+    /// The number of groups opened but not yet closed in this message.
+    group_depth: usize,
+}
+
+/// What a decoder does with a byte that does not begin any known instruction, such as an
+/// opcode or word added in a later version of the encoding.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum UnknownBytes {
+    /// Decoding fails with `UnexpectedByte`.
+    #[default]
+    Error,
+    /// The byte is skipped, as if it were an instruction that writes nothing.
+    Skip,
+    /// The byte is decoded as the replacement character `\u{fffd}`, spaced like a word,
+    /// so that the reader knows that some content could not be shown.
+    Placeholder,
 }
 
-impl<T: fmt::Write> Decoder<T> {
+/// The text written in place of each unknown byte by `UnknownBytes::Placeholder`.
+const PLACEHOLDER: &str = "\u{fffd}";
+
+/// The default limit on the length of the text written by a decoder: one gibibyte.
+pub const DEFAULT_MAX_OUTPUT_LEN: usize = 1 << 30;
+
+impl<'d, T: fmt::Write> Decoder<'d, T> {
     /// Decodes input bytes and writes the resultant text to the given writer.
     pub fn new(writer: T) -> Self {
+        Self::with_dict_set(writer, &DICT_SET)
+    }
+
+    /// Decodes input bytes using the words in the given dictionary set.
+    /// This must be the same dictionary set that the encoder used.
+    pub fn with_dict_set(writer: T, dict_set: &'d DictionarySet<'d>) -> Self {
         Self {
             writer,
+            dict_set,
             state: EncodingState::default(),
+            pending: Vec::new(),
+            rendering: Rendering::Active,
+            output_len: 0,
+            max_output_len: DEFAULT_MAX_OUTPUT_LEN,
+            position: 0,
+            resyncing: false,
+            unknown_bytes: UnknownBytes::Error,
+            dangling_attach: false,
+            variation_hint: None,
+            max_group_depth: None,
+            group_depth: 0,
         }
     }
 
+    /// Decodes input bytes, rendering words in the given variation until the stream sets
+    /// another. Words are encoded the same way in every variation, so the same bytes can
+    /// be decoded in any variation, and streams need no SetVariation instruction if both
+    /// ends agree on the variation beforehand.
+    pub fn with_variation(writer: T, variation: Variation) -> Self {
+        Self::with_variation_and_dict_set(writer, variation, &DICT_SET)
+    }
+
+    /// Decodes input bytes, rendering words in the given variation as for
+    /// `with_variation`, using the words in the given dictionary set.
+    pub fn with_variation_and_dict_set(
+        writer: T,
+        variation: Variation,
+        dict_set: &'d DictionarySet<'d>,
+    ) -> Self {
+        let mut decoder = Self::with_dict_set(writer, dict_set);
+        decoder.state.variation = variation;
+        decoder.state.initial_variation = variation;
+        decoder
+    }
+
+    /// Limits the total length of the text this decoder writes, in bytes.
+    /// Once the limit would be exceeded, decoding fails with `OutputLimitExceeded`,
+    /// so that decoding untrusted input cannot exhaust memory. None of the text of the
+    /// instruction that would exceed the limit is written, however the bytes are read.
+    /// Defaults to `DEFAULT_MAX_OUTPUT_LEN`.
+    pub fn max_output_len(&mut self, max_output_len: usize) -> &mut Self {
+        self.max_output_len = max_output_len;
+        self
+    }
+
+    /// Chooses what to do with bytes that do not begin any known instruction.
+    /// Bytes that are unknown are each assumed to be a whole instruction, which may not be
+    /// true of a later version of the encoding, so lenient decoding is a best effort.
+    /// Defaults to `UnknownBytes::Error`.
+    pub fn unknown_bytes(&mut self, unknown_bytes: UnknownBytes) -> &mut Self {
+        self.unknown_bytes = unknown_bytes;
+        self
+    }
+
+    /// Writes reduplicated words rendered in sitelen pona, such as the second `lili` of
+    /// `lili lili`, as the given marker rather than repeating the glyph. A word is only
+    /// reduplicated if it follows the same word with nothing but a space between them.
+    /// Words in other variations are always repeated. Defaults to None.
+    pub fn reduplication_marker(&mut self, marker: Option<&'static str>) -> &mut Self {
+        self.state.reduplication_marker = marker;
+        self
+    }
+
+    /// Writes each Abbreviation instruction as the shorthand it was written as, such as
+    /// `tp`, rather than the words it stands for. Defaults to false.
+    pub fn contract_abbreviations(&mut self, enabled: bool) -> &mut Self {
+        self.state.contract_abbreviations = enabled;
+        self
+    }
+
+    /// Requires the groups of each message to be balanced and nested at most this deep.
+    /// Opening a group beyond the limit fails with `NestingTooDeep`, and closing a group
+    /// that was never opened, or ending a message or the stream with a group still open,
+    /// fails with `UnmatchedGroup`. Groups are decoded without recursion, so this guards
+    /// against malformed streams rather than protecting the stack. A cartouche counts as one
+    /// more group inside the groups around it. Cartouches hold only words, so a cartouche
+    /// opened inside another fails with `NestingTooDeep` if it would be beyond the limit,
+    /// and otherwise, like a cartouche cut off by the end of a message or the stream, with
+    /// `UnmatchedGroup`. By default, groups are not checked, since the encoder writes the
+    /// brackets of its input as they are, balanced or not.
+    pub fn max_group_depth(&mut self, limit: Option<usize>) -> &mut Self {
+        self.max_group_depth = limit;
+        self
+    }
+
+    /// Writes the given marker before every word but the first of each message, after any
+    /// space, so that the decoded text can be split into words even where words are
+    /// attached together or written without spaces, as in hanzi. Names in cartouches are
+    /// marked like words; raw text and punctuation are not. Defaults to None.
+    pub fn word_boundary(&mut self, marker: Option<&'static str>) -> &mut Self {
+        self.state.word_boundary = marker;
+        self
+    }
+
+    /// Writes each of the given words as its glyph in the map, in whichever variation it
+    /// is rendered, such as to use a personal style of sitelen pona for some words. The
+    /// rest are rendered from the dictionary set as usual. In interlinear and ruby text,
+    /// the glyph replaces the main spelling of a word, and the gloss is unchanged; in ruby
+    /// text, it is escaped like raw text. By default, no words are overridden.
+    pub fn glyph_overrides(&mut self, overrides: HashMap<WordIdentifier, &str>) -> &mut Self {
+        self.state.glyph_overrides = overrides
+            .into_iter()
+            .map(|(word, glyph)| (word, glyph.to_owned()))
+            .collect();
+        self
+    }
+
+    /// Decodes input bytes as interlinear text, which is useful for learners.
+    /// Each word is written in the first variation, followed by the second variation in
+    /// parentheses, such as `󱤴(mi)` for `(Variation::SitelenPona, Variation::Default)`.
+    /// SetVariation instructions in the stream are ignored.
+    pub fn interlinear(writer: T, variations: (Variation, Variation)) -> Self {
+        Self::interlinear_with_dict_set(writer, variations, &DICT_SET)
+    }
+
+    /// Decodes input bytes as interlinear text, as for `interlinear`, using the words in
+    /// the given dictionary set.
+    pub fn interlinear_with_dict_set(
+        writer: T,
+        (first, second): (Variation, Variation),
+        dict_set: &'d DictionarySet<'d>,
+    ) -> Self {
+        let mut decoder = Self::with_dict_set(writer, dict_set);
+        decoder.rendering = Rendering::Interlinear(first, second);
+        decoder
+    }
+
+    /// Decodes input bytes as HTML, writing each word as a hanzi character annotated with
+    /// its reading in the default orthography, such as `<ruby>言<rt>toki</rt></ruby>`.
+    /// Words without a hanzi character are written in the default orthography instead.
+    /// SetVariation instructions in the stream are ignored, and raw text is escaped.
+    pub fn ruby(writer: T) -> Self {
+        Self::ruby_with_dict_set(writer, &DICT_SET)
+    }
+
+    /// Decodes input bytes as HTML, as for `ruby`, using the words in the given
+    /// dictionary set.
+    pub fn ruby_with_dict_set(writer: T, dict_set: &'d DictionarySet<'d>) -> Self {
+        let mut decoder = Self::with_dict_set(writer, dict_set);
+        decoder.rendering = Rendering::Ruby(Variation::Hanzi);
+        decoder
+    }
+
     /// Process the given text and output the encoded text to the writer.
-    pub fn read_bytes(&mut self, bytes: &[u8]) {
-        for byte in bytes {
-            self.read_byte(*byte);
+    /// The text is written in batches, rather than once per word.
+    /// If an error occurs, the text decoded before it has already been written, and
+    /// `position` is just past the bytes of the instruction that could not be decoded.
+    pub fn read_bytes(&mut self, mut bytes: &[u8]) -> Result<(), DecodeError> {
+        // Complete any instruction left over from a previous call, or skip bytes that
+        // are not words if resynchronising.
+        while !self.pending.is_empty() || self.resyncing {
+            match bytes.split_first() {
+                Some((byte, rest)) => {
+                    self.read_byte(*byte)?;
+                    bytes = rest;
+                }
+                None => return Ok(()),
+            }
         }
-    }
 
-    pub fn read_byte(&mut self, byte: u8) {
-        match byte {
-            0x21 => self.execute(Instruction::AttachToPrevious),
-            _ if 0x22 <= byte => {
-                // This is a single-byte toki pona word.
-                self.execute(Instruction::TokiPonaWord {
-                    word: DICT_SET.word_from_bytes(&[byte - 0x22]),
+        let mut batch = String::new();
+        let mut invalid = false;
+        let result = loop {
+            let (instruction, len) = match Instruction::decode(bytes, self.dict_set) {
+                Ok(Some(decoded)) => decoded,
+                Ok(None) => {
+                    // Keep the start of an incomplete instruction for the next call.
+                    self.pending.extend_from_slice(bytes);
+                    self.position += bytes.len();
+                    break Ok(());
+                }
+                Err(DecodeError::UnexpectedByte(_))
+                    if self.unknown_bytes != UnknownBytes::Error =>
+                {
+                    match self.unknown_instruction() {
+                        Some(instruction) => (instruction, 1),
+                        None => {
+                            bytes = &bytes[1..];
+                            self.position += 1;
+                            continue;
+                        }
+                    }
+                }
+                Err(err) => {
+                    invalid = true;
+                    break Err(err);
+                }
+            };
+            bytes = &bytes[len..];
+            self.position += len;
+            self.track(&instruction);
+            if let Some(limit) = self.max_group_depth {
+                if let Err(err) = self.check_groups(&instruction, limit) {
+                    break Err(err);
+                }
+            }
+            let batch_len = batch.len();
+            self.state
+                .execute(instruction, self.dict_set, self.rendering, |text| {
+                    batch.push_str(&text);
+                    Ok(())
+                })
+                .expect("collecting text cannot fail");
+            if self.output_len + batch.len() > self.max_output_len {
+                // None of this instruction's text is written.
+                batch.truncate(batch_len);
+                break Err(DecodeError::OutputLimitExceeded {
+                    limit: self.max_output_len,
                 });
             }
-            _ => panic!("unexpected byte {:#x?}", byte),
+            if batch.len() >= DECODE_BATCH_SIZE {
+                self.writer.write_str(&batch)?;
+                self.output_len += batch.len();
+                batch.clear();
+            }
+        };
+        // Text decoded before an error is still written.
+        self.writer.write_str(&batch)?;
+        self.output_len += batch.len();
+        if invalid {
+            // Decode the invalid instruction again byte by byte, so that exactly the bytes
+            // it spans are consumed and counted in the position.
+            for byte in bytes {
+                self.read_byte(*byte)?;
+            }
+        }
+        result
+    }
+
+    /// Returns the number of bytes read so far, across every call to this decoder.
+    /// After an error, this is the offset just past the bytes that caused it.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Recovers from an error as well as possible, such as in a corrupted stream.
+    /// Any partly read instruction is discarded, and subsequent bytes are skipped until
+    /// the next byte that is a word, from which decoding continues as normal.
+    /// This is a best-effort heuristic: the recovered text may be missing words, or
+    /// contain words that were not in the original stream.
+    pub fn resync(&mut self) {
+        self.pending.clear();
+        self.resyncing = true;
+    }
+
+    pub fn read_byte(&mut self, byte: u8) -> Result<(), DecodeError> {
+        self.process_byte(byte)?;
+        Ok(())
+    }
+
+    /// Decodes at most `n` instructions from the given bytes, and returns the number of
+    /// bytes consumed, so that text can be decoded a few words at a time. An instruction
+    /// left incomplete at the end of the bytes is kept, as if by `read_bytes`, and does not
+    /// count towards `n`. If an error occurs, `position` shows how far decoding reached.
+    pub fn read_n(&mut self, bytes: &[u8], n: usize) -> Result<usize, DecodeError> {
+        let mut decoded = 0;
+        let mut consumed = 0;
+        while decoded < n && consumed < bytes.len() {
+            self.process_byte(bytes[consumed])?;
+            consumed += 1;
+            if self.pending.is_empty() && !self.resyncing {
+                decoded += 1;
+            }
+        }
+        Ok(consumed)
+    }
+
+    /// Reads and decodes bytes until an EndOfStream instruction or the end of the reader.
+    /// No bytes after the EndOfStream instruction are read, so the next message can be
+    /// decoded by calling this method again; the reader should be buffered for speed.
+    /// Returns true if an EndOfStream instruction was read, and false if the reader ended.
+    pub fn read_from<R: io::Read>(&mut self, reader: &mut R) -> Result<bool, DecodeError> {
+        let mut byte = [0];
+        loop {
+            match reader.read(&mut byte) {
+                Ok(0) => return Ok(false),
+                Ok(_) => {
+                    if self.process_byte(byte[0])? {
+                        return Ok(true);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(DecodeError::Read(err.kind())),
+            }
+        }
+    }
+
+    /// Processes a single byte, returning true if it completed an EndOfStream instruction.
+    fn process_byte(&mut self, byte: u8) -> Result<bool, DecodeError> {
+        self.position += 1;
+        if self.resyncing {
+            if byte < self.dict_set.first_word_byte() {
+                return Ok(false);
+            }
+            self.resyncing = false;
+        }
+        self.pending.push(byte);
+        match Instruction::decode(&self.pending, self.dict_set) {
+            Ok(Some((instruction, len))) => {
+                self.pending.drain(..len);
+                let end_of_stream = matches!(instruction, Instruction::EndOfStream);
+                self.execute(instruction)?;
+                Ok(end_of_stream)
+            }
+            Ok(None) => Ok(false),
+            Err(DecodeError::UnexpectedByte(_)) if self.unknown_bytes != UnknownBytes::Error => {
+                // An unknown byte is always the first byte of an instruction.
+                self.pending.clear();
+                if let Some(instruction) = self.unknown_instruction() {
+                    self.execute(instruction)?;
+                }
+                Ok(false)
+            }
+            Err(DecodeError::InvalidCartouche) => {
+                let err = self.cartouche_error();
+                self.pending.clear();
+                Err(err)
+            }
+            Err(err) => {
+                self.pending.clear();
+                Err(err)
+            }
+        }
+    }
+
+    /// Returns the error for the cartouche at the start of the pending bytes, which could not
+    /// be decoded. If groups are checked, a cartouche opened inside it, or the end of the
+    /// message, is a group error rather than the usual `InvalidCartouche`.
+    fn cartouche_error(&self) -> DecodeError {
+        let Some(limit) = self.max_group_depth else {
+            return DecodeError::InvalidCartouche;
+        };
+        // This is the byte that ended the cartouche, unless it held too many words.
+        let byte = self
+            .pending
+            .iter()
+            .skip(1)
+            .take(MAX_CARTOUCHE_WORDS + 1)
+            .find(|byte| self.dict_set.word_from_bytes(&[**byte]).is_none());
+        match byte.and_then(|byte| self.dict_set.opcode(*byte)) {
+            Some(Opcode::CartoucheOpen) if self.group_depth + 1 >= limit => {
+                DecodeError::NestingTooDeep { limit }
+            }
+            Some(Opcode::CartoucheOpen | Opcode::EndOfStream) => DecodeError::UnmatchedGroup,
+            _ => DecodeError::InvalidCartouche,
         }
     }
 
-    fn execute(&mut self, instruction: Instruction) {
+    /// Returns the instruction that an unknown byte is decoded as, if any.
+    fn unknown_instruction(&self) -> Option<Instruction> {
+        match self.unknown_bytes {
+            UnknownBytes::Placeholder => Some(Instruction::RawText {
+                text: PLACEHOLDER.to_owned(),
+            }),
+            UnknownBytes::Error | UnknownBytes::Skip => None,
+        }
+    }
+
+    /// Checks that the stream ended cleanly, rather than partway through an instruction or
+    /// with an AttachToPrevious instruction that has no text after it to attach.
+    /// Either of these usually means that the stream was cut off, and returns
+    /// `TruncatedStream`, or `TruncatedPayload` if the stream ended partway through the
+    /// text of a length-prefixed instruction such as RawText.
+    /// Dropping the decoder instead does not check the stream.
+    pub fn finish(self) -> Result<(), DecodeError> {
+        let open_cartouche =
+            self.pending.first() == Some(&self.dict_set.opcode_byte(Opcode::CartoucheOpen));
+        if self.max_group_depth.is_some()
+            && (self.group_depth > 0 && self.pending.is_empty() || open_cartouche)
+        {
+            Err(DecodeError::UnmatchedGroup)
+        } else if self.pending.is_empty() && !self.dangling_attach {
+            Ok(())
+        } else {
+            Err(truncation_error(&self.pending, self.dict_set))
+        }
+    }
+
+    /// Returns the variation that the stream's VariationHint instruction says the message
+    /// is written in, if one has been read, so that a reader can tell which script a
+    /// message is in. Streams written without a hint return None.
+    pub fn variation_hint(&self) -> Option<Variation> {
+        self.variation_hint
+    }
+
+    /// Counts the groups that this instruction opens or closes, returning an error if they
+    /// are nested deeper than the limit or unbalanced.
+    fn check_groups(&mut self, instruction: &Instruction, limit: usize) -> Result<(), DecodeError> {
         match instruction {
-            Instruction::TokiPonaWord { word } => {
-                if self.state.prepend_space {
-                    self.write(" ");
+            Instruction::GroupOpen | Instruction::Cartouche { .. } if self.group_depth >= limit => {
+                return Err(DecodeError::NestingTooDeep { limit })
+            }
+            Instruction::GroupOpen => self.group_depth += 1,
+            Instruction::GroupClose => {
+                self.group_depth = self
+                    .group_depth
+                    .checked_sub(1)
+                    .ok_or(DecodeError::UnmatchedGroup)?;
+            }
+            Instruction::EndOfStream if self.group_depth > 0 => {
+                return Err(DecodeError::UnmatchedGroup)
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Records whether this instruction leaves an AttachToPrevious instruction dangling,
+    /// and any variation hint it gives.
+    fn track(&mut self, instruction: &Instruction) {
+        if let Instruction::VariationHint { variation } = instruction {
+            self.variation_hint = Some(*variation);
+        }
+        match instruction {
+            Instruction::AttachToPrevious => self.dangling_attach = true,
+            // These instructions affect the next word, so do not attach anything.
+            Instruction::SetVariation { .. }
+            | Instruction::VariationHint { .. }
+            | Instruction::Capitalize
+            | Instruction::CaseMask { .. } => {}
+            _ => self.dangling_attach = false,
+        }
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), DecodeError> {
+        self.track(&instruction);
+        if let Some(limit) = self.max_group_depth {
+            self.check_groups(&instruction, limit)?;
+        }
+        // An instruction's text is written whole or not at all, as in `read_bytes`.
+        let mut pieces = Vec::new();
+        self.state
+            .execute(instruction, self.dict_set, self.rendering, |piece| {
+                pieces.push(piece);
+                Ok(())
+            })
+            .expect("collecting text cannot fail");
+        let len = pieces.iter().map(|piece| piece.len()).sum::<usize>();
+        if self.output_len + len > self.max_output_len {
+            return Err(DecodeError::OutputLimitExceeded {
+                limit: self.max_output_len,
+            });
+        }
+        self.output_len += len;
+        for piece in pieces {
+            self.writer.write_str(&piece)?;
+        }
+        Ok(())
+    }
+}
+
+/// Iterates over the instructions encoded in a byte slice.
+/// After an error is returned, the iterator ends.
+#[derive(Debug)]
+pub struct Instructions<'a> {
+    bytes: &'a [u8],
+    dict_set: &'a DictionarySet<'a>,
+}
+
+impl<'a> Instructions<'a> {
+    /// Iterates over the instructions encoded in the given bytes,
+    /// using the words in the given dictionary set.
+    pub fn new(bytes: &'a [u8], dict_set: &'a DictionarySet<'a>) -> Self {
+        Self { bytes, dict_set }
+    }
+}
+
+/// Iterates over the instructions encoded in the given bytes.
+pub fn instructions(bytes: &[u8]) -> Instructions<'_> {
+    Instructions::new(bytes, &DICT_SET)
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Result<Instruction, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.bytes.is_empty() {
+            return None;
+        }
+        let result = match Instruction::decode(self.bytes, self.dict_set) {
+            Ok(Some((instruction, len))) => {
+                self.bytes = &self.bytes[len..];
+                return Some(Ok(instruction));
+            }
+            Ok(None) => Err(truncation_error(self.bytes, self.dict_set)),
+            Err(err) => Err(err),
+        };
+        self.bytes = &[];
+        Some(result)
+    }
+}
+
+/// Renders each instruction in the given bytes in a human-readable form, such as
+/// `["WORD(toki)", "ATTACH", "SET_VARIATION(tp_ZH)"]`. This is useful for debugging.
+pub fn disassemble(bytes: &[u8]) -> Result<Vec<String>, DecodeError> {
+    disassemble_with(bytes, &DICT_SET)
+}
+
+/// Renders each instruction in the given bytes as `disassemble` does, using the words
+/// and opcodes of the given dictionary set.
+pub fn disassemble_with(
+    bytes: &[u8],
+    dict_set: &DictionarySet,
+) -> Result<Vec<String>, DecodeError> {
+    Instructions::new(bytes, dict_set)
+        .map(|instruction| Ok(instruction?.disassemble(dict_set)))
+        .collect()
+}
+
+/// Splits the given bytes into chunks of at most `max_chunk` bytes, each ending on an
+/// instruction boundary, for transports that limit the size of each message.
+/// Feeding the chunks in order to a single decoder decodes the whole text, since
+/// state such as the active variation carries over from one chunk to the next.
+/// If a single instruction is longer than `max_chunk`, an error is returned.
+pub fn split_at_boundaries(bytes: &[u8], max_chunk: usize) -> Result<Vec<&[u8]>, DecodeError> {
+    split_at_boundaries_with(bytes, max_chunk, &DICT_SET)
+}
+
+/// Splits the given bytes into chunks as `split_at_boundaries` does, using the words and
+/// opcodes of the given dictionary set.
+pub fn split_at_boundaries_with<'b>(
+    bytes: &'b [u8],
+    max_chunk: usize,
+    dict_set: &DictionarySet,
+) -> Result<Vec<&'b [u8]>, DecodeError> {
+    let mut chunks = Vec::new();
+    let mut chunk_start = 0;
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let len = match Instruction::decode(&bytes[offset..], dict_set)? {
+            Some((_, len)) => len,
+            None => return Err(truncation_error(&bytes[offset..], dict_set)),
+        };
+        if len > max_chunk {
+            return Err(DecodeError::InstructionTooLong { len });
+        }
+        if offset + len - chunk_start > max_chunk {
+            chunks.push(&bytes[chunk_start..offset]);
+            chunk_start = offset;
+        }
+        offset += len;
+    }
+    if chunk_start < bytes.len() {
+        chunks.push(&bytes[chunk_start..]);
+    }
+    Ok(chunks)
+}
+
+/// A run of instructions in the difference between two streams; see `diff`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InstructionDiff {
+    /// These instructions are in both streams.
+    Equal(Vec<Instruction>),
+    /// These instructions are only in the new stream.
+    Insert(Vec<Instruction>),
+    /// These instructions are only in the old stream.
+    Delete(Vec<Instruction>),
+}
+
+/// Whether an instruction is kept, inserted or deleted by a diff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// Compares two streams instruction by instruction, returning the runs of instructions
+/// that are kept, inserted or deleted to turn the old stream into the new one. Unlike a
+/// diff of the bytes, inserting a word only inserts that word, although the bytes after
+/// it have moved. The diff keeps as many instructions as possible. It takes time in
+/// proportion to the product of the streams' lengths, less any common start and end.
+pub fn diff(old: &[u8], new: &[u8]) -> Result<Vec<InstructionDiff>, DecodeError> {
+    diff_with(old, new, &DICT_SET)
+}
+
+/// Compares two streams as `diff` does, using the words and opcodes of the given
+/// dictionary set.
+pub fn diff_with(
+    old: &[u8],
+    new: &[u8],
+    dict_set: &DictionarySet,
+) -> Result<Vec<InstructionDiff>, DecodeError> {
+    let old = Instructions::new(old, dict_set).collect::<Result<Vec<_>, _>>()?;
+    let new = Instructions::new(new, dict_set).collect::<Result<Vec<_>, _>>()?;
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_middle = &old[prefix..old.len() - suffix];
+    let new_middle = &new[prefix..new.len() - suffix];
+
+    // The length of the longest common subsequence of each pair of suffixes.
+    let width = new_middle.len() + 1;
+    let mut common = vec![0; (old_middle.len() + 1) * width];
+    for i in (0..old_middle.len()).rev() {
+        for j in (0..new_middle.len()).rev() {
+            common[i * width + j] = if old_middle[i] == new_middle[j] {
+                common[(i + 1) * width + j + 1] + 1
+            } else {
+                common[(i + 1) * width + j].max(common[i * width + j + 1])
+            };
+        }
+    }
+
+    let mut changes = Vec::new();
+    changes.extend(
+        old[..prefix]
+            .iter()
+            .map(|instruction| (Change::Equal, instruction)),
+    );
+    let (mut i, mut j) = (0, 0);
+    while i < old_middle.len() || j < new_middle.len() {
+        if i < old_middle.len() && j < new_middle.len() && old_middle[i] == new_middle[j] {
+            changes.push((Change::Equal, &old_middle[i]));
+            i += 1;
+            j += 1;
+        } else if j < new_middle.len()
+            && (i == old_middle.len() || common[i * width + j + 1] >= common[(i + 1) * width + j])
+        {
+            changes.push((Change::Insert, &new_middle[j]));
+            j += 1;
+        } else {
+            changes.push((Change::Delete, &old_middle[i]));
+            i += 1;
+        }
+    }
+    changes.extend(
+        old[old.len() - suffix..]
+            .iter()
+            .map(|instruction| (Change::Equal, instruction)),
+    );
+
+    // Adjacent changes of the same kind form a single run.
+    let mut runs: Vec<(Change, Vec<Instruction>)> = Vec::new();
+    for (change, instruction) in changes {
+        match runs.last_mut() {
+            Some((kind, instructions)) if *kind == change => {
+                instructions.push(instruction.clone());
+            }
+            _ => runs.push((change, vec![instruction.clone()])),
+        }
+    }
+    Ok(runs
+        .into_iter()
+        .map(|(change, instructions)| match change {
+            Change::Equal => InstructionDiff::Equal(instructions),
+            Change::Insert => InstructionDiff::Insert(instructions),
+            Change::Delete => InstructionDiff::Delete(instructions),
+        })
+        .collect())
+}
+
+/// Counts how many times each word occurs in the given bytes.
+/// Words are counted regardless of the variation they are written in.
+pub fn word_histogram(bytes: &[u8]) -> Result<HashMap<WordIdentifier, usize>, DecodeError> {
+    word_histogram_with(bytes, &DICT_SET)
+}
+
+/// Counts how many times each word occurs as `word_histogram` does, using the words and
+/// opcodes of the given dictionary set.
+pub fn word_histogram_with(
+    bytes: &[u8],
+    dict_set: &DictionarySet,
+) -> Result<HashMap<WordIdentifier, usize>, DecodeError> {
+    let mut histogram = HashMap::new();
+    for instruction in Instructions::new(bytes, dict_set) {
+        for word in instruction?.expanded_words(dict_set)? {
+            *histogram.entry(word).or_insert(0) += 1;
+        }
+    }
+    Ok(histogram)
+}
+
+/// Counts the instructions in the given bytes, such as words, attachments and variation
+/// changes, which may be fewer than the bytes since some instructions take several.
+/// A Phrase instruction counts once, however many words it prints.
+pub fn instruction_count(bytes: &[u8]) -> Result<usize, DecodeError> {
+    instruction_count_with(bytes, &DICT_SET)
+}
+
+/// Counts the instructions in the given bytes as `instruction_count` does, using the words
+/// and opcodes of the given dictionary set.
+pub fn instruction_count_with(
+    bytes: &[u8],
+    dict_set: &DictionarySet,
+) -> Result<usize, DecodeError> {
+    Instructions::new(bytes, dict_set).try_fold(0, |count, instruction| {
+        instruction?;
+        Ok(count + 1)
+    })
+}
+
+/// Decodes text incrementally, yielding each piece of text as it is produced.
+/// Dictionary words are borrowed rather than copied into a single output string.
+/// The space before a word is yielded as a separate piece.
+#[derive(Debug)]
+pub struct DecodeStream<'a> {
+    instructions: Instructions<'a>,
+    state: EncodingState,
+    /// Text produced by the last instruction that has not yet been yielded.
+    queue: VecDeque<Cow<'a, str>>,
+}
+
+impl<'a> DecodeStream<'a> {
+    /// Decodes the given bytes incrementally, using the words in the given dictionary set.
+    pub fn new(bytes: &'a [u8], dict_set: &'a DictionarySet<'a>) -> Self {
+        Self {
+            instructions: Instructions::new(bytes, dict_set),
+            state: EncodingState::default(),
+            queue: VecDeque::new(),
+        }
+    }
+}
+
+/// Decodes the given bytes incrementally; see [`DecodeStream`].
+pub fn decode_stream(bytes: &[u8]) -> DecodeStream<'_> {
+    DecodeStream::new(bytes, &DICT_SET)
+}
+
+impl<'a> Iterator for DecodeStream<'a> {
+    type Item = Result<Cow<'a, str>, DecodeError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(text) = self.queue.pop_front() {
+                return Some(Ok(text));
+            }
+            let instruction = match self.instructions.next()? {
+                Ok(instruction) => instruction,
+                Err(err) => return Some(Err(err)),
+            };
+            let queue = &mut self.queue;
+            self.state
+                .execute(
+                    instruction,
+                    self.instructions.dict_set,
+                    Rendering::Active,
+                    |text| {
+                        queue.push_back(text);
+                        Ok(())
+                    },
+                )
+                .expect("collecting text cannot fail");
+        }
+    }
+}
+
+/// Encodes the given text, returning the encoded bytes.
+pub fn encode(text: &str) -> Result<Vec<u8>, EncodeError> {
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.write_text(text)?;
+    encoder.finish()?;
+    Ok(encoded)
+}
+
+/// Checks whether the given text can be encoded, without stopping at the first problem.
+/// Each token that cannot be encoded is skipped, and reported as an `EncodeError::At`
+/// containing the byte offset of the token in the text.
+/// Returns an empty list if and only if `encode` would succeed.
+pub fn validate_all(text: &str) -> Vec<EncodeError> {
+    let mut errors = Vec::new();
+    let mut encoder = Encoder::new(io::sink());
+    let mut report = |result, offset: usize| {
+        if let Err(error) = result {
+            // Point at the token itself, not the space before it.
+            let offset = offset + (text.as_bytes().get(offset) == Some(&b' ')) as usize;
+            errors.push(EncodeError::At {
+                offset,
+                error: Box::new(error),
+            });
+        }
+    };
+    for c in text.chars() {
+        // If this character causes an error, it was in the currently buffered token.
+        let offset = encoder.unencoded_offset;
+        report(encoder.write_character(c), offset);
+    }
+    let offset = encoder.unencoded_offset;
+    report(encoder.finish(), offset);
+    errors
+}
+
+/// Encodes the given words, each spaced normally, without converting them to text first.
+/// This is the counterpart of reading words from an instruction iterator.
+pub fn encode_identifiers(words: &[WordIdentifier]) -> Result<Vec<u8>, EncodeError> {
+    let mut encoded = Vec::new();
+    for word in words {
+        encoded
+            .extend(Instruction::TokiPonaWord { word: *word }.encode(&DICT_SET, Layout::Compact)?);
+    }
+    Ok(encoded)
+}
+
+/// Decodes the given bytes into a list of instructions, which can be edited and then
+/// encoded again with `encode_instructions`.
+pub fn decode_to_instructions(bytes: &[u8]) -> Result<Vec<Instruction>, DecodeError> {
+    instructions(bytes).collect()
+}
+
+/// Encodes the given instructions, the inverse of `decode_to_instructions`.
+/// Words are encoded in the compact layout, so bytes in the debug layout are not
+/// reproduced exactly, although they decode to the same text.
+/// Phrase and Abbreviation instructions must refer to an entry of the phrase or
+/// abbreviation table, as they would not decode otherwise.
+pub fn encode_instructions(instructions: &[Instruction]) -> Result<Vec<u8>, EncodeError> {
+    let mut encoded = Vec::new();
+    for instruction in instructions {
+        instruction
+            .expanded_words(&DICT_SET)
+            .map_err(|err| match err {
+                DecodeError::UnknownPhrase(code) => EncodeError::UnknownPhrase(code),
+                DecodeError::UnknownAbbreviation(code) => EncodeError::UnknownAbbreviation(code),
+                _ => unreachable!("only phrases and abbreviations are expanded"),
+            })?;
+        encoded.extend(instruction.encode(&DICT_SET, Layout::Compact)?);
+    }
+    Ok(encoded)
+}
+
+/// Encodes the given text, returning the encoded bytes as padded base64.
+/// This is safe to send through channels that only accept text, such as JSON strings.
+pub fn encode_base64(text: &str) -> Result<String, EncodeError> {
+    Ok(base64::encode(&encode(text)?, base64::STANDARD, true))
+}
+
+/// Encodes the given text, returning the encoded bytes as unpadded URL-safe base64.
+pub fn encode_base64_url(text: &str) -> Result<String, EncodeError> {
+    Ok(base64::encode(&encode(text)?, base64::URL_SAFE, false))
+}
+
+/// Decodes text produced by `encode_base64`. Padding is optional.
+pub fn decode_base64(text: &str) -> Result<String, DecodeError> {
+    let bytes = base64::decode(text, base64::STANDARD)
+        .map_err(|offset| DecodeError::InvalidBase64 { offset })?;
+    decode(&bytes)
+}
+
+/// Decodes text produced by `encode_base64_url`. Padding is optional.
+pub fn decode_base64_url(text: &str) -> Result<String, DecodeError> {
+    let bytes = base64::decode(text, base64::URL_SAFE)
+        .map_err(|offset| DecodeError::InvalidBase64 { offset })?;
+    decode(&bytes)
+}
+
+/// Returns the words that spell out the given number in nasin nanpa pona, such as
+/// `luka tu` for seven; see `NumeralSystem::NasinNanpaPona`.
+/// Returns None if the number is larger than `numerals::MAX_SPELT_NUMBER`.
+pub fn number_to_words(number: u64) -> Option<Vec<WordIdentifier>> {
+    let words = NumeralSystem::NasinNanpaPona.spell(number)?;
+    Some(
+        words
+            .into_iter()
+            .map(|word| {
+                DICT_SET
+                    .get_identifier(word)
+                    .expect("numerals should be words")
+            })
+            .collect(),
+    )
+}
+
+/// Returns the number spelt out by the given words in nasin nanpa pona, the inverse of
+/// `number_to_words`. Returns None if the words are not a number in that system.
+pub fn words_to_number(words: &[WordIdentifier]) -> Option<u64> {
+    let words = words
+        .iter()
+        .map(|word| DICT_SET.word_str(*word))
+        .collect::<Vec<_>>();
+    NumeralSystem::NasinNanpaPona.value(&words)
+}
+
+/// Encodes the given text, returning the encoded bytes as lower case hexadecimal, two
+/// digits per byte, such as `57597e` for `mi moku seme`.
+pub fn encode_hex(text: &str) -> Result<String, EncodeError> {
+    Ok(encode(text)?
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+/// Decodes text produced by `encode_hex`. Upper case digits are also accepted.
+pub fn decode_hex(text: &str) -> Result<String, DecodeError> {
+    let digits = text
+        .bytes()
+        .enumerate()
+        .map(|(offset, byte)| {
+            (byte as char)
+                .to_digit(16)
+                .ok_or(DecodeError::InvalidHex { offset })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // A single digit left over does not make up a whole byte.
+    if digits.len() % 2 == 1 {
+        return Err(DecodeError::InvalidHex {
+            offset: digits.len() - 1,
+        });
+    }
+    let bytes = digits
+        .chunks(2)
+        .map(|pair| (pair[0] << 4 | pair[1]) as u8)
+        .collect::<Vec<_>>();
+    decode(&bytes)
+}
+
+/// Decodes the given bytes, returning the decoded text.
+/// The bytes must be a whole stream: if they end partway through an instruction, such as
+/// raw text shorter than its length says, `TruncatedPayload` is returned; see
+/// `Decoder::finish`.
+pub fn decode(bytes: &[u8]) -> Result<String, DecodeError> {
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.read_bytes(bytes)?;
+    decoder.finish()?;
+    Ok(decoded)
+}
+
+/// Encodes as much of the given text as fits in `max_bytes`, stopping before the first
+/// word that would not fit, so that the bytes end on an instruction boundary. Returns the
+/// bytes and the number of characters of the text they encode. The rest of the text,
+/// starting with the space before the next word, can be encoded in another message.
+/// If a word that is reached cannot be encoded, the error is returned in an
+/// `EncodeError::At` with the byte offset of the word, so that the text before it can be
+/// encoded instead. See `encode_budget_marked` to show that text was cut off.
+pub fn encode_budget(text: &str, max_bytes: usize) -> Result<(Vec<u8>, usize), EncodeError> {
+    let (mut encoded, cuts) = budget_cuts(text, max_bytes)?;
+    let (fitted_bytes, fitted_chars) = cuts
+        .last()
+        .map_or((0, 0), |cut| (cut.bytes_end, cut.chars_end));
+    encoded.truncate(fitted_bytes);
+    Ok((encoded, fitted_chars))
+}
+
+/// Where the encoding of a text may be cut off after one of its words.
+struct BudgetCut {
+    /// The length of the encoded bytes up to and including the word.
+    bytes_end: usize,
+    /// The number of characters of the text up to and including the word.
+    chars_end: usize,
+    /// The byte offsets in the text of the start of the word, after the space before it,
+    /// and of its end.
+    word: Range<usize>,
+}
+
+/// Encodes the given text word by word until a word would not fit in `max_bytes`,
+/// returning the bytes written and where they may be cut off after each word that fits.
+fn budget_cuts(text: &str, max_bytes: usize) -> Result<(Vec<u8>, Vec<BudgetCut>), EncodeError> {
+    let mut encoder = Encoder::new(Vec::new());
+    let mut cuts = Vec::new();
+    let (mut start, mut chars_end) = (0, 0);
+    while start != text.len() {
+        // Each word is written with the space before it.
+        let rest = &text[start..];
+        let first = rest.chars().next().map_or(0, char::len_utf8);
+        let end = start + rest[first..].find(' ').map_or(rest.len(), |i| i + first);
+        let word = &text[start..end];
+        let word_start = start + usize::from(word.starts_with(' '));
+        encoder
+            .write_text(word)
+            .and_then(|()| encoder.flush())
+            .map_err(|err| EncodeError::At {
+                offset: word_start,
+                error: Box::new(err),
+            })?;
+        if encoder.writer.len() > max_bytes {
+            break;
+        }
+        chars_end += word.chars().count();
+        cuts.push(BudgetCut {
+            bytes_end: encoder.writer.len(),
+            chars_end,
+            word: word_start..end,
+        });
+        start = end;
+    }
+    Ok((std::mem::take(&mut encoder.writer), cuts))
+}
+
+/// Encodes as much of the given text as fits in `max_bytes`, as `encode_budget` does, but if
+/// the text is cut off, a space and the given marker, such as `"…"`, are encoded after the
+/// last word that fits, and the bytes including the marker fit in `max_bytes`. Words are
+/// left out to make room for the marker if necessary. The number of characters returned
+/// counts only the characters of the text, not the space or the marker.
+/// If the budget is too small for the first word and the marker together, no bytes and no
+/// characters are returned, as a marker alone would not say what was cut off; callers
+/// that need something sent must check for this.
+pub fn encode_budget_marked(
+    text: &str,
+    max_bytes: usize,
+    marker: &str,
+) -> Result<(Vec<u8>, usize), EncodeError> {
+    let (encoded, cuts) = budget_cuts(text, max_bytes)?;
+    if cuts
+        .last()
+        .map_or(text.is_empty(), |cut| cut.word.end == text.len())
+    {
+        return Ok((encoded, text.chars().count()));
+    }
+    for cut in cuts.iter().rev() {
+        // The marker takes about as many bytes after any word, so its length is estimated
+        // from the last word before it alone, and only a cut it fits after is encoded in
+        // full. An estimate that cannot be made skips no cuts.
+        let word = &text[cut.word.clone()];
+        let marker_len = encode(&format!("{} {}", word, marker))
+            .and_then(|marked| Ok(marked.len().saturating_sub(encode(word)?.len())))
+            .unwrap_or(0);
+        if cut.bytes_end + marker_len > max_bytes {
+            continue;
+        }
+        let marked = encode(&format!("{} {}", &text[..cut.word.end], marker))?;
+        if marked.len() <= max_bytes {
+            return Ok((marked, cut.chars_end));
+        }
+    }
+    Ok((Vec::new(), 0))
+}
+
+/// Returns the canonical form of the given text, which is the text that decoding its
+/// encoding with the default options produces. Text is canonical, and so round-trips
+/// exactly, if it is equal to its canonical form. Text that cannot be encoded, such as
+/// text with two spaces between words, has no canonical form and returns an error.
+pub fn normalize(text: &str) -> Result<String, EncodeError> {
+    let encoded = encode(text)?;
+    Ok(decode(&encoded).expect("encoded text should decode"))
+}
+
+/// Returns true if the given bytes are exactly what encoding their decoded text with the
+/// default options produces, so that the same text always has the same bytes. Streams
+/// with redundant instructions, such as an AttachToPrevious before punctuation, or that
+/// spell a word the compact layout would write as a byte, are not canonical. Returns an
+/// error if the bytes do not decode.
+pub fn is_canonical(bytes: &[u8]) -> Result<bool, DecodeError> {
+    let decoded = decode(bytes)?;
+    Ok(encode(&decoded).is_ok_and(|encoded| encoded == bytes))
+}
+
+/// Decodes the given bytes, rendering every word in the target variation.
+///
+/// Encoded words do not depend on the variation they were written in; only their rendering
+/// at decode time does. Transcoding is therefore just decoding with a chosen variation.
+/// Any SetVariation instructions in the stream are ignored in favour of the target.
+/// As for `decode`, the bytes must be a whole stream.
+pub fn transcode(bytes: &[u8], target: Variation) -> Result<String, DecodeError> {
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.rendering = Rendering::Override(target);
+    decoder.read_bytes(bytes)?;
+    decoder.finish()?;
+    Ok(decoded)
+}
+
+/// Decodes the given bytes, rendering every word in the target variation as `transcode`
+/// does, and returns the text between each pair of spaces as a separate element, so that
+/// each glyph can be placed individually. Words attached together, and the punctuation
+/// attached to them, form a single element, such as `"󱥬󱥔."` for `tokipona.`.
+pub fn decode_graphemes(bytes: &[u8], target: Variation) -> Result<Vec<String>, DecodeError> {
+    let mut elements = Vec::new();
+    let mut current = String::new();
+    let mut state = EncodingState::default();
+    for instruction in instructions(bytes) {
+        state
+            .execute(
+                instruction?,
+                &DICT_SET,
+                Rendering::Override(target),
+                |text| {
+                    if text == " " {
+                        if !current.is_empty() {
+                            elements.push(std::mem::take(&mut current));
+                        }
+                    } else {
+                        current.push_str(&text);
+                    }
+                    Ok(())
+                },
+            )
+            .expect("collecting text cannot fail");
+    }
+    if !current.is_empty() {
+        elements.push(current);
+    }
+    Ok(elements)
+}
+
+/// A word decoded by `decode_rich`, with where it came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedWord {
+    /// The text of the word as it is printed, including any capitalisation.
+    pub text: String,
+    /// The word in the dictionary set.
+    pub id: WordIdentifier,
+    /// The variation the word was rendered in.
+    pub variation: Variation,
+    /// True if the word was printed directly after the text before it, with no space.
+    pub attached_to_previous: bool,
+}
+
+/// Decodes the given bytes into the words they print, each with its identifier and the
+/// variation it was rendered in, so that a renderer can map each word it shows back to
+/// the dictionary. Each word of a phrase is listed separately. Text that is not a word,
+/// such as punctuation or raw text, is not listed, but a word printed directly after it
+/// is attached to it.
+pub fn decode_rich(bytes: &[u8]) -> Result<Vec<RenderedWord>, DecodeError> {
+    decode_rich_with(bytes, &DICT_SET)
+}
+
+/// Decodes the given bytes into the words they print as `decode_rich` does, using the words
+/// and opcodes of the given dictionary set.
+pub fn decode_rich_with(
+    bytes: &[u8],
+    dict_set: &DictionarySet,
+) -> Result<Vec<RenderedWord>, DecodeError> {
+    let mut words = Vec::new();
+    let mut state = EncodingState::default();
+    // True if the last text printed was a space, or nothing has been printed in the message.
+    let mut after_space = true;
+    for instruction in Instructions::new(bytes, dict_set) {
+        let instruction = instruction?;
+        let ids = match instruction {
+            Instruction::TokiPonaWord { .. }
+            | Instruction::Phrase { .. }
+            | Instruction::Abbreviation { .. } => instruction.expanded_words(dict_set)?,
+            _ => {
+                if instruction == Instruction::EndOfStream {
+                    after_space = true;
                 }
-                self.write(DICT_SET.get_word_variation(word, self.state.variation));
-                self.state.prepend_space = true;
+                state
+                    .execute(instruction, dict_set, Rendering::Active, |text| {
+                        if !text.is_empty() {
+                            after_space = text == " ";
+                        }
+                        Ok(())
+                    })
+                    .expect("collecting text cannot fail");
+                continue;
             }
-            Instruction::AttachToPrevious => {
-                self.state.prepend_space = false;
+        };
+        for (i, id) in ids.into_iter().enumerate() {
+            // The words of a phrase are always spaced.
+            if i > 0 {
+                state.prepend_space = true;
             }
+            let variation = state.variation;
+            let mut text = String::new();
+            let mut spaced = false;
+            state
+                .execute(
+                    Instruction::TokiPonaWord { word: id },
+                    dict_set,
+                    Rendering::Active,
+                    |piece| {
+                        if piece == " " && text.is_empty() {
+                            spaced = true;
+                        } else {
+                            text.push_str(&piece);
+                        }
+                        Ok(())
+                    },
+                )
+                .expect("collecting text cannot fail");
+            words.push(RenderedWord {
+                text,
+                id,
+                variation,
+                attached_to_previous: !spaced && !after_space,
+            });
+            after_space = false;
         }
     }
+    Ok(words)
+}
+
+/// Decodes the given bytes into a fixed-size buffer, returning the number of bytes written.
+/// If the decoded text does not fit, as much of it as possible is written, and
+/// `DecodeError::BufferFull` reports how many bytes that was.
+/// A multi-byte character is never split across the end of the buffer.
+/// As with `Decoder::finish`, a stream that is cut off is an error.
+pub fn decode_into(bytes: &[u8], out: &mut [u8]) -> Result<usize, DecodeError> {
+    let mut writer = SliceWriter {
+        out,
+        written: 0,
+        full: false,
+    };
+    let mut decoder = Decoder::new(&mut writer);
+    let result = decoder.read_bytes(bytes).and_then(|()| decoder.finish());
+    match result {
+        Ok(()) => Ok(writer.written),
+        Err(DecodeError::Write(_)) if writer.full => Err(DecodeError::BufferFull {
+            written: writer.written,
+        }),
+        Err(err) => Err(err),
+    }
+}
 
-    fn write(&mut self, string: &str) {
-        self.writer.write_str(string).expect("writing failed")
+/// Writes text into a fixed-size buffer.
+/// Once a character does not fit, an error is returned and no more text is written.
+struct SliceWriter<'a> {
+    out: &'a mut [u8],
+    written: usize,
+    full: bool,
+}
+
+impl fmt::Write for SliceWriter<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for c in s.chars() {
+            let len = c.len_utf8();
+            if self.full || self.written + len > self.out.len() {
+                self.full = true;
+                return Err(fmt::Error);
+            }
+            c.encode_utf8(&mut self.out[self.written..]);
+            self.written += len;
+        }
+        Ok(())
     }
 }
@@ -0,0 +1,280 @@
+use std::{error::Error, fmt, io};
+
+use crate::{dict_set::WordIdentifier, variation::Variation};
+
+/// An error that occurred while encoding text.
+#[derive(Debug)]
+pub enum EncodeError {
+    /// The given character sequence was not a word in the dictionary set.
+    UnknownWord(String),
+    /// This word's index in the dictionary set cannot be represented in a single byte.
+    /// The dictionary set contains too many words.
+    DictionaryTooLarge { index: usize },
+    /// This word identifier does not refer to a word in the dictionary set,
+    /// for instance because it was created by a different dictionary set.
+    UnknownIdentifier(WordIdentifier),
+    /// This text is too long to be stored in a single raw text instruction.
+    RawTextTooLong { len: usize },
+    /// This cartouche has more words than the `MAX_CARTOUCHE_WORDS` a cartouche may hold.
+    CartoucheTooLong { len: usize },
+    /// This number is too large to be spelt out in toki pona words.
+    NumberTooLarge(String),
+    /// A Phrase instruction referred to a phrase that does not exist,
+    /// or that contains a word that is not in the dictionary set.
+    UnknownPhrase(u8),
+    /// An Abbreviation instruction referred to an abbreviation that does not exist,
+    /// or that stands for a word that is not in the dictionary set.
+    UnknownAbbreviation(u8),
+    /// This text has an upper case letter, but the encoder assumes its input is lower case.
+    UpperCase(String),
+    /// Text written to the encoder as bytes was not valid UTF-8, or ended partway through
+    /// a character.
+    InvalidUtf8,
+    /// The underlying writer returned an error.
+    Io(io::Error),
+    /// The given error was caused by the text starting at this byte offset in the input.
+    At {
+        offset: usize,
+        error: Box<EncodeError>,
+    },
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EncodeError::UnknownWord(word) => {
+                write!(f, "encoding failed for character sequence [{}]", word)
+            }
+            EncodeError::DictionaryTooLarge { index } => {
+                write!(f, "word index {} does not fit in a single byte", index)
+            }
+            EncodeError::UnknownIdentifier(word) => {
+                write!(
+                    f,
+                    "word {}:{} is not in the dictionary set",
+                    word.dict, word.word
+                )
+            }
+            EncodeError::RawTextTooLong { len } => {
+                write!(f, "raw text of {} bytes is too long", len)
+            }
+            EncodeError::CartoucheTooLong { len } => {
+                write!(f, "cartouche of {} words is too long", len)
+            }
+            EncodeError::NumberTooLarge(number) => {
+                write!(f, "number {} is too large to spell out", number)
+            }
+            EncodeError::UnknownPhrase(code) => write!(f, "unknown phrase {:#x?}", code),
+            EncodeError::UnknownAbbreviation(code) => {
+                write!(f, "unknown abbreviation {:#x?}", code)
+            }
+            EncodeError::UpperCase(text) => write!(f, "[{}] is not lower case", text),
+            EncodeError::InvalidUtf8 => write!(f, "text is not valid UTF-8"),
+            EncodeError::Io(err) => write!(f, "writing failed: {}", err),
+            EncodeError::At { offset, error } => write!(f, "at byte {}: {}", offset, error),
+        }
+    }
+}
+
+impl Error for EncodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EncodeError::Io(err) => Some(err),
+            EncodeError::At { error, .. } => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for EncodeError {
+    fn from(err: io::Error) -> Self {
+        EncodeError::Io(err)
+    }
+}
+
+/// Encoding errors other than `Io` become errors of kind `InvalidData`.
+impl From<EncodeError> for io::Error {
+    fn from(err: EncodeError) -> Self {
+        match err {
+            EncodeError::Io(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// An error that occurred while decoding bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// This byte does not begin any known instruction.
+    UnexpectedByte(u8),
+    /// A SetVariation instruction referred to a variation that does not exist.
+    UnknownVariation(u8),
+    /// A Phrase instruction referred to a phrase that does not exist,
+    /// or that contains a word that is not in the dictionary set.
+    UnknownPhrase(u8),
+    /// An Abbreviation instruction referred to an abbreviation that does not exist,
+    /// or that stands for a word that is not in the dictionary set.
+    UnknownAbbreviation(u8),
+    /// The text of a RawText, PunctuationRun or SpeltWord instruction was not valid UTF-8.
+    InvalidUtf8,
+    /// A SpeltWord instruction contained text that is not a word in the dictionary set.
+    UnknownSpelling(String),
+    /// The bytes ended partway through an instruction.
+    TruncatedStream,
+    /// A cartouche contained a byte that is not a word, or more words than the
+    /// `MAX_CARTOUCHE_WORDS` a cartouche may hold.
+    InvalidCartouche,
+    /// The bytes ended partway through a RawText, PunctuationRun, SpeltWord, CaseMask or
+    /// CodeSpan instruction, whose length claimed more bytes than remained.
+    TruncatedPayload { claimed: usize, remaining: usize },
+    /// The output buffer was too small to hold the decoded text.
+    /// The given number of bytes were written before the buffer filled up.
+    BufferFull { written: usize },
+    /// The text was not valid base64. The invalid character is at this byte offset.
+    InvalidBase64 { offset: usize },
+    /// The text was not valid hexadecimal. The invalid character is at this byte offset.
+    InvalidHex { offset: usize },
+    /// An instruction of this many bytes was longer than the largest chunk allowed.
+    InstructionTooLong { len: usize },
+    /// The decoded text would have been longer than the decoder's limit of this many bytes.
+    OutputLimitExceeded { limit: usize },
+    /// Groups were nested deeper than the decoder's limit of this many.
+    NestingTooDeep { limit: usize },
+    /// A group was closed without being opened, or was still open at the end of a message.
+    UnmatchedGroup,
+    /// The underlying reader returned an error of this kind.
+    Read(io::ErrorKind),
+    /// The underlying writer returned an error.
+    Write(fmt::Error),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedByte(byte) => write!(f, "unexpected byte {:#x?}", byte),
+            DecodeError::UnknownVariation(byte) => write!(f, "unknown variation {:#x?}", byte),
+            DecodeError::UnknownPhrase(code) => write!(f, "unknown phrase {:#x?}", code),
+            DecodeError::UnknownAbbreviation(code) => {
+                write!(f, "unknown abbreviation {:#x?}", code)
+            }
+            DecodeError::InvalidUtf8 => write!(f, "text is not valid UTF-8"),
+            DecodeError::UnknownSpelling(word) => write!(f, "unknown word [{}]", word),
+            DecodeError::TruncatedStream => {
+                write!(f, "stream ended partway through an instruction")
+            }
+            DecodeError::InvalidCartouche => write!(f, "cartouche is not a list of words"),
+            DecodeError::TruncatedPayload { claimed, remaining } => write!(
+                f,
+                "instruction claimed {} bytes but only {} remained",
+                claimed, remaining
+            ),
+            DecodeError::BufferFull { written } => {
+                write!(f, "output buffer full after {} bytes", written)
+            }
+            DecodeError::InvalidBase64 { offset } => {
+                write!(f, "invalid base64 at byte {}", offset)
+            }
+            DecodeError::InvalidHex { offset } => {
+                write!(f, "invalid hexadecimal at byte {}", offset)
+            }
+            DecodeError::InstructionTooLong { len } => {
+                write!(f, "instruction of {} bytes does not fit in a chunk", len)
+            }
+            DecodeError::OutputLimitExceeded { limit } => {
+                write!(f, "decoded text longer than the limit of {} bytes", limit)
+            }
+            DecodeError::NestingTooDeep { limit } => {
+                write!(f, "groups nested deeper than the limit of {}", limit)
+            }
+            DecodeError::UnmatchedGroup => write!(f, "group opened or closed without a match"),
+            DecodeError::Read(kind) => write!(f, "reading failed: {}", kind),
+            DecodeError::Write(err) => write!(f, "writing failed: {}", err),
+        }
+    }
+}
+
+impl Error for DecodeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DecodeError::Write(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<fmt::Error> for DecodeError {
+    fn from(err: fmt::Error) -> Self {
+        DecodeError::Write(err)
+    }
+}
+
+/// An error that occurred while building a dictionary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DictionaryError {
+    /// This word is spelt the same way as another word in the same variation,
+    /// so it could not be told apart when encoding.
+    DuplicateWord { word: String, variation: Variation },
+    /// The CSV text was empty, so had no header row naming the variations.
+    MissingHeaders,
+    /// The first column of the CSV header was not the default variation, `tp`.
+    MissingDefaultColumn,
+    /// The CSV header named a variation that does not exist.
+    UnknownVariation { name: String },
+    /// This line of the CSV text, counting from 1, had the wrong number of columns.
+    WrongColumnCount {
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// This line of the CSV text, counting from 1, contained an upper case letter.
+    /// Dictionaries store words in lower case only.
+    UpperCaseWord { line: usize },
+    /// The control opcodes of a dictionary set, starting at this base, would overlap its
+    /// words, starting at this byte, or would leave no byte for any word.
+    ByteRangesOverlap {
+        opcode_base: u8,
+        first_word_byte: u8,
+    },
+}
+
+impl fmt::Display for DictionaryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DictionaryError::DuplicateWord { word, variation } => write!(
+                f,
+                "word [{}] appears twice in variation {}",
+                word,
+                <&str>::from(*variation)
+            ),
+            DictionaryError::MissingHeaders => write!(f, "expected headers listing variations"),
+            DictionaryError::MissingDefaultColumn => {
+                write!(f, "expected the first column to be the default variation")
+            }
+            DictionaryError::UnknownVariation { name } => {
+                write!(f, "[{}] is not a known variation", name)
+            }
+            DictionaryError::WrongColumnCount {
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "line {} has {} columns, but the header has {}",
+                line, found, expected
+            ),
+            DictionaryError::UpperCaseWord { line } => {
+                write!(f, "line {} has an upper case letter", line)
+            }
+            DictionaryError::ByteRangesOverlap {
+                opcode_base,
+                first_word_byte,
+            } => write!(
+                f,
+                "opcodes from {:#x?} do not fit below words from {:#x?}",
+                opcode_base, first_word_byte
+            ),
+        }
+    }
+}
+
+impl Error for DictionaryError {}
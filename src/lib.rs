@@ -2,3 +2,10 @@ pub mod dict;
 pub mod variation;
 pub mod dict_set;
 pub mod encoding;
+pub mod error;
+pub mod punctuation;
+mod tokenizer;
+mod base64;
+pub mod numerals;
+pub mod phrases;
+pub mod abbreviations;
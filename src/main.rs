@@ -1,15 +1,35 @@
-use toki_pona_encoding::encoding::{Decoder, Encoder};
+use std::io::Read;
+
+use toki_pona_encoding::encoding::{disassemble, Decoder, Encoder};
 
 fn main() {
+    // With `--disasm`, print the instructions in an encoded stream read from stdin.
+    if std::env::args().skip(1).any(|arg| arg == "--disasm") {
+        let mut bytes = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut bytes)
+            .expect("could not read stdin");
+        for instruction in disassemble(&bytes).expect("decoding failed") {
+            println!("{}", instruction);
+        }
+        return;
+    }
+
     let mut encoded = Vec::new();
     let mut encoder = Encoder::new(&mut encoded);
-    encoder.write_text("alasa akesi a lukin oko");
-    drop(encoder);
+    encoder
+        .write_text("alasa akesi a lukin oko")
+        .expect("encoding failed");
+    encoder.finish().expect("encoding failed");
 
     println!("encoded: {:x?}", encoded);
+    println!(
+        "instructions: {:?}",
+        disassemble(&encoded).expect("decoding failed")
+    );
 
     let mut decoded = String::new();
     let mut decoder = Decoder::new(&mut decoded);
-    decoder.read_bytes(&encoded);
+    decoder.read_bytes(&encoded).expect("decoding failed");
     println!("decoded: {}", decoded);
 }
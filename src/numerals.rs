@@ -0,0 +1,78 @@
+/// A system for writing numbers as toki pona words.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NumeralSystem {
+    /// The system described in pu, using `wan` and `tu`.
+    /// Numbers from five upwards cannot be written exactly, so are written as `mute`.
+    Pu,
+    /// The additive system known as nasin nanpa pona, where `ali` is one hundred,
+    /// `mute` is twenty, and `luka` is five; seven is written `luka tu`.
+    NasinNanpaPona,
+}
+
+/// The largest number that can be spelt out with the additive system.
+/// Larger numbers would need an impractical number of repetitions of `ali`.
+pub const MAX_SPELT_NUMBER: u64 = 10_000;
+
+impl NumeralSystem {
+    /// Returns the words that spell out the given number in this system, such as
+    /// `["luka", "tu"]` for seven in nasin nanpa pona.
+    /// Zero is `ala` in both systems.
+    /// Returns None if the number is larger than `MAX_SPELT_NUMBER`.
+    pub fn spell(self, number: u64) -> Option<Vec<&'static str>> {
+        if number > MAX_SPELT_NUMBER {
+            return None;
+        }
+        if number == 0 {
+            return Some(vec!["ala"]);
+        }
+        if self == NumeralSystem::Pu && number >= 5 {
+            return Some(vec!["mute"]);
+        }
+        let mut remaining = number;
+        let mut words = Vec::new();
+        for (value, word) in self.values() {
+            while remaining >= *value {
+                words.push(*word);
+                remaining -= value;
+            }
+        }
+        Some(words)
+    }
+
+    /// Returns the number spelt out by the given words in this system, the inverse of
+    /// `spell`, such as seven for `["luka", "tu"]` in nasin nanpa pona. The words must be
+    /// exactly those that `spell` gives, so `tu tu tu` is not six, as six is `luka wan`.
+    /// Returns None if the words do not spell an exact number, such as `mute` in pu, or
+    /// spell a number larger than `MAX_SPELT_NUMBER`.
+    pub fn value(self, words: &[&str]) -> Option<u64> {
+        if words == ["ala"] {
+            return Some(0);
+        }
+        let mut number = 0;
+        for word in words {
+            let (value, _) = self.values().iter().find(|(_, w)| w == word)?;
+            number += value;
+            if number > MAX_SPELT_NUMBER {
+                return None;
+            }
+        }
+        if self.spell(number)? != words {
+            return None;
+        }
+        Some(number)
+    }
+
+    /// Returns the value of each word used to spell out numbers, from largest to smallest.
+    fn values(self) -> &'static [(u64, &'static str)] {
+        match self {
+            NumeralSystem::Pu => &[(2, "tu"), (1, "wan")],
+            NumeralSystem::NasinNanpaPona => &[
+                (100, "ali"),
+                (20, "mute"),
+                (5, "luka"),
+                (2, "tu"),
+                (1, "wan"),
+            ],
+        }
+    }
+}
@@ -0,0 +1,44 @@
+use crate::dict_set::{DictionarySet, WordIdentifier};
+
+/// Common phrases, such as the `tenpo ni la` that begins a `la` clause, which may each be
+/// encoded as a single two-byte Phrase instruction rather than a byte per word.
+/// Only phrases of at least three words are listed, since shorter phrases would not be
+/// any smaller. A phrase's code is its index in this list, so new phrases must be added
+/// at the end.
+pub const PHRASES: &[&str] = &[
+    "tenpo ni la",
+    "tenpo kama la",
+    "tenpo pini la",
+    "tenpo ali la",
+    "tenpo mute la",
+    "tenpo suno ni la",
+    "tenpo pimeja ni la",
+    "tenpo ni la mi",
+    "tan ni la",
+    "ni li pona",
+    "ni li ike",
+    "mi wile e ni",
+    "mi sona ala",
+    "mi pilin e ni",
+    "mi pilin pona",
+    "mi olin e sina",
+    "mi kama sona",
+    "sina pona anu seme",
+    "sina sona ala sona",
+    "toki pona li",
+    "jan pona mi",
+    "ali li pona",
+    "pona tawa sina",
+    "o awen pona",
+    "o tawa pona",
+];
+
+/// Returns the words of the phrase with the given code, looked up in the dictionary set.
+/// Returns None if there is no such phrase, or a word of it is not in the dictionary set.
+pub fn words(dict_set: &DictionarySet, code: u8) -> Option<Vec<WordIdentifier>> {
+    PHRASES
+        .get(code as usize)?
+        .split(' ')
+        .map(|word| dict_set.get_identifier(word))
+        .collect()
+}
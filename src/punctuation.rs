@@ -0,0 +1,50 @@
+/// A punctuation mark that may appear between words.
+/// Punctuation attaches to the previous word, and is followed by a space
+/// before the next word.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Punctuation {
+    FullStop,
+    Comma,
+    Colon,
+    QuestionMark,
+    ExclamationMark,
+}
+
+impl From<Punctuation> for &'static str {
+    fn from(value: Punctuation) -> Self {
+        match value {
+            Punctuation::FullStop => ".",
+            Punctuation::Comma => ",",
+            Punctuation::Colon => ":",
+            Punctuation::QuestionMark => "?",
+            Punctuation::ExclamationMark => "!",
+        }
+    }
+}
+
+impl From<Punctuation> for char {
+    fn from(value: Punctuation) -> Self {
+        match value {
+            Punctuation::FullStop => '.',
+            Punctuation::Comma => ',',
+            Punctuation::Colon => ':',
+            Punctuation::QuestionMark => '?',
+            Punctuation::ExclamationMark => '!',
+        }
+    }
+}
+
+impl TryFrom<char> for Punctuation {
+    type Error = ();
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        Ok(match value {
+            '.' => Self::FullStop,
+            ',' => Self::Comma,
+            ':' => Self::Colon,
+            '?' => Self::QuestionMark,
+            '!' => Self::ExclamationMark,
+            _ => return Err(()),
+        })
+    }
+}
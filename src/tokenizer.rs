@@ -0,0 +1,183 @@
+/// A piece of a token, which is a run of text containing no spaces.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Piece<'a> {
+    /// Text that should be looked up in the dictionary set.
+    Text(&'a str),
+    /// A single emoji, possibly made of several code points,
+    /// such as a flag or a ZWJ sequence.
+    Emoji(&'a str),
+}
+
+/// Splits a token into the punctuation clinging to its start, the text between,
+/// and the punctuation clinging to its end, such as `"(toki"` into `"("`, `"toki"` and `""`.
+/// If the token is only punctuation, all of it is returned as the leading part.
+pub(crate) fn strip_punctuation(token: &str) -> (&str, &str, &str) {
+    let core_start = token
+        .find(|c| !is_clinging_punctuation(c))
+        .unwrap_or(token.len());
+    let core_end = token[core_start..]
+        .rfind(|c| !is_clinging_punctuation(c))
+        .map_or(core_start, |i| {
+            core_start + i + token[core_start + i..].chars().next().unwrap().len_utf8()
+        });
+    (
+        &token[..core_start],
+        &token[core_start..core_end],
+        &token[core_end..],
+    )
+}
+
+/// Returns true if the buffered text is a word followed by punctuation, and this character
+/// would begin another word, such as the `p` in `toki,pona`.
+/// The buffer must then be split before this character, since punctuation only clings to
+/// the start and end of a token.
+pub(crate) fn ends_punctuation(buffer: &[char], c: char) -> bool {
+    !is_clinging_punctuation(c)
+        && buffer
+            .last()
+            .is_some_and(|&last| is_clinging_punctuation(last))
+        && buffer
+            .iter()
+            .any(|&c| c != ' ' && !is_clinging_punctuation(c))
+}
+
+/// Returns true if this character is punctuation that is never part of a word,
+/// such as a full stop, a bracket or a quotation mark.
+/// Apostrophes are not included, since they are misspellings of a word rather than
+/// punctuation; see the encoder's `normalize_diacritics` option.
+pub(crate) fn is_clinging_punctuation(c: char) -> bool {
+    (c.is_ascii_punctuation() && c != '\'')
+        || matches!(
+            c,
+            '\u{2018}'
+                | '\u{201a}'..='\u{201f}'
+                | '\u{2013}'
+                | '\u{2014}'
+                | '\u{2026}'
+                | '\u{ab}'
+                | '\u{bb}'
+                | '\u{3001}'
+                | '\u{3002}'
+                | '\u{300c}'..='\u{300f}'
+                | '\u{ff01}'
+                | '\u{ff0c}'
+                | '\u{ff1a}'
+                | '\u{ff1b}'
+                | '\u{ff1f}'
+        )
+}
+
+/// Returns true if appending this character to the buffered text would continue the
+/// buffer's last grapheme cluster, in which case the buffer must not be split before it.
+/// Without the `graphemes` feature, every character is treated as its own grapheme.
+#[cfg(feature = "graphemes")]
+pub(crate) fn continues_grapheme(buffer: &[char], c: char) -> bool {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    let mut text = buffer.iter().collect::<String>();
+    let last_grapheme_start = match text.grapheme_indices(true).next_back() {
+        Some((i, _)) => i,
+        None => return false,
+    };
+    text.push(c);
+    text[last_grapheme_start..].graphemes(true).count() == 1
+}
+
+#[cfg(not(feature = "graphemes"))]
+pub(crate) fn continues_grapheme(_buffer: &[char], _c: char) -> bool {
+    false
+}
+
+/// Splits a token into emoji and the text between them.
+/// Each emoji is its own piece, even if it is not separated from the text by spaces.
+/// With the `graphemes` feature, an emoji is any grapheme cluster beginning with an emoji.
+#[cfg(feature = "graphemes")]
+pub(crate) fn split_emoji(token: &str) -> Vec<Piece<'_>> {
+    use unicode_segmentation::UnicodeSegmentation;
+
+    let mut pieces = Vec::new();
+    let mut text_start = 0;
+    for (start, grapheme) in token.grapheme_indices(true) {
+        if !grapheme.starts_with(is_emoji) {
+            continue;
+        }
+        if text_start < start {
+            pieces.push(Piece::Text(&token[text_start..start]));
+        }
+        pieces.push(Piece::Emoji(grapheme));
+        text_start = start + grapheme.len();
+    }
+    if text_start < token.len() {
+        pieces.push(Piece::Text(&token[text_start..]));
+    }
+    pieces
+}
+
+/// Splits a token into emoji and the text between them.
+/// Each emoji is its own piece, even if it is not separated from the text by spaces.
+#[cfg(not(feature = "graphemes"))]
+pub(crate) fn split_emoji(token: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut text_start = 0;
+    let mut chars = token.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if !is_emoji(c) {
+            continue;
+        }
+
+        // Consume every code point that extends this emoji.
+        let regional_indicator = is_regional_indicator(c);
+        let mut end = start + c.len_utf8();
+        while let Some(&(i, next)) = chars.peek() {
+            let extends = is_emoji_modifier(next)
+                || (regional_indicator && is_regional_indicator(next) && end - start == 4)
+                || (token[start..end].ends_with('\u{200d}') && is_emoji(next));
+            if !extends {
+                break;
+            }
+            end = i + next.len_utf8();
+            chars.next();
+        }
+
+        if text_start < start {
+            pieces.push(Piece::Text(&token[text_start..start]));
+        }
+        pieces.push(Piece::Emoji(&token[start..end]));
+        text_start = end;
+    }
+    if text_start < token.len() {
+        pieces.push(Piece::Text(&token[text_start..]));
+    }
+    pieces
+}
+
+/// Returns true if this code point begins an emoji.
+fn is_emoji(c: char) -> bool {
+    matches!(c,
+        '\u{1f000}'..='\u{1faff}'
+        | '\u{2600}'..='\u{27bf}'
+        | '\u{2b00}'..='\u{2bff}'
+        | '\u{2190}'..='\u{21ff}'
+        | '\u{2300}'..='\u{23ff}'
+    )
+}
+
+/// Returns true if this code point continues the emoji before it, such as a skin tone,
+/// a variation selector, a zero width joiner, or a keycap.
+#[cfg(not(feature = "graphemes"))]
+fn is_emoji_modifier(c: char) -> bool {
+    matches!(c,
+        '\u{1f3fb}'..='\u{1f3ff}'
+        | '\u{fe0e}'
+        | '\u{fe0f}'
+        | '\u{200d}'
+        | '\u{20e3}'
+        | '\u{e0020}'..='\u{e007f}'
+    )
+}
+
+/// Regional indicators are emoji that combine in pairs to form flags.
+#[cfg(not(feature = "graphemes"))]
+fn is_regional_indicator(c: char) -> bool {
+    matches!(c, '\u{1f1e6}'..='\u{1f1ff}')
+}
@@ -1,16 +1,37 @@
 /// Each variation is a specific orthographic system for toki pona.
 /// These orthographic systems may each contain their own options for
 /// customising the text that is encoded.
-#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+/// Encoders and decoders start in `Variation::default()`, the default orthography.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum Variation {
+    /// The default orthography, written in the Latin alphabet.
+    #[default]
     Default,
+    /// tipunsin, an abbreviated orthography.
     Tipunsin,
+    /// Words written as CJK characters.
     Hanzi,
+    /// sitelen pona glyphs, written using the UCSUR code points.
+    SitelenPona,
 }
 
-impl Default for Variation {
-    fn default() -> Self {
-        Self::Default
+impl Variation {
+    /// Returns true if dictionaries store words in this variation, which depends on the
+    /// `variation-*` features. The default orthography is always enabled. Words in a
+    /// disabled variation are written in the default orthography instead.
+    pub fn is_enabled(self) -> bool {
+        match self {
+            Variation::Default => true,
+            Variation::Tipunsin => cfg!(feature = "variation-tipunsin"),
+            Variation::Hanzi => cfg!(feature = "variation-hanzi"),
+            Variation::SitelenPona => cfg!(feature = "variation-sitelen"),
+        }
+    }
+
+    /// Returns true if text in this variation is usually written without spaces between
+    /// words, as hanzi are, so that encoders split it into words by its glyphs instead.
+    pub fn is_unspaced(self) -> bool {
+        matches!(self, Variation::Hanzi)
     }
 }
 
@@ -23,6 +44,46 @@ impl<'a> TryFrom<&'a str> for Variation {
             "tp" => Self::Default,
             "tp_S" => Self::Tipunsin,
             "tp_ZH" => Self::Hanzi,
+            "tp_UCSUR" => Self::SitelenPona,
+            _ => return Err(()),
+        })
+    }
+}
+
+/// Converts to variation codes such as "tp_ZH".
+impl From<Variation> for &'static str {
+    fn from(value: Variation) -> Self {
+        match value {
+            Variation::Default => "tp",
+            Variation::Tipunsin => "tp_S",
+            Variation::Hanzi => "tp_ZH",
+            Variation::SitelenPona => "tp_UCSUR",
+        }
+    }
+}
+
+/// Converts to the byte used to represent this variation in encoded text.
+impl From<Variation> for u8 {
+    fn from(value: Variation) -> Self {
+        match value {
+            Variation::Default => 0,
+            Variation::Tipunsin => 1,
+            Variation::Hanzi => 2,
+            Variation::SitelenPona => 3,
+        }
+    }
+}
+
+/// Converts from the byte used to represent a variation in encoded text.
+impl TryFrom<u8> for Variation {
+    type Error = ();
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        Ok(match value {
+            0 => Self::Default,
+            1 => Self::Tipunsin,
+            2 => Self::Hanzi,
+            3 => Self::SitelenPona,
             _ => return Err(()),
         })
     }
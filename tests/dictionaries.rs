@@ -0,0 +1,556 @@
+use toki_pona_encoding::{
+    dict::Dictionary,
+    dict_set::{ByteMeaning, DictionarySet, WordIdentifier, DICT_SET},
+    encoding::{Decoder, Encoder, Opcode, FIRST_WORD_BYTE},
+};
+
+#[test]
+#[cfg(feature = "nimi-sin")]
+fn nimi_sin_words_are_encodable() {
+    use toki_pona_encoding::variation::Variation;
+
+    for word in ["kokosila", "jasima", "kipisi"] {
+        let identifier = DICT_SET.get_identifier(word).expect("word not found");
+        let bytes = DICT_SET.word_to_bytes(identifier).unwrap();
+        assert_eq!(bytes.len(), 1);
+        assert_eq!(
+            DICT_SET.get_word_variation(
+                DICT_SET.word_from_bytes(&bytes).unwrap(),
+                Variation::default()
+            ),
+            word
+        );
+    }
+}
+
+#[test]
+#[cfg(feature = "nimi-sin")]
+fn nimi_sin_bytes_follow_pu() {
+    // Each word's byte is fixed by the number of pu words and its line in nimi_sin.csv,
+    // so that reordering either file is caught.
+    let pu_words = toki_pona_encoding::dict::PU.default.words.len() as u8;
+    for (word, index) in [("epiku", 0), ("kipisi", 3), ("soko", 14), ("tonsi", 16)] {
+        let identifier = DICT_SET.get_identifier(word).expect("word not found");
+        assert_eq!(
+            DICT_SET.word_to_bytes(identifier).unwrap(),
+            [FIRST_WORD_BYTE + pu_words + index]
+        );
+    }
+}
+
+#[test]
+#[cfg(not(feature = "nimi-sin"))]
+fn nimi_sin_words_are_not_encodable_by_default() {
+    assert!(DICT_SET.get_identifier("kokosila").is_none());
+}
+
+#[test]
+fn flat_indices_count_every_word_in_order() {
+    for (index, word) in DICT_SET.words().enumerate() {
+        assert_eq!(DICT_SET.flat_index(word), Some(index));
+        assert_eq!(DICT_SET.from_flat_index(index), Some(word));
+    }
+    let count = DICT_SET.words().count();
+    assert_eq!(DICT_SET.from_flat_index(count), None);
+    assert_eq!(
+        DICT_SET.flat_index(WordIdentifier { dict: 99, word: 0 }),
+        None
+    );
+}
+
+#[test]
+fn byte_map_has_no_collisions() {
+    // Building the map panics if two features claim the same byte.
+    let map = DICT_SET.byte_map();
+    assert_eq!(map.len(), 256);
+    assert!(matches!(
+        map[&0x21],
+        ByteMeaning::Opcode(Opcode::AttachToPrevious)
+    ));
+    assert!(matches!(map[&FIRST_WORD_BYTE], ByteMeaning::Word(_)));
+    assert!(matches!(
+        map[&0x00],
+        ByteMeaning::Opcode(Opcode::EndOfStream)
+    ));
+    assert!(matches!(map[&0x01], ByteMeaning::Free));
+}
+
+#[test]
+fn custom_dictionary_set() {
+    let dict = Dictionary::from_csv("tp\nsoweli\nkala\n");
+    let set = DictionarySet::new(vec![&dict]);
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::with_dict_set(&mut encoded, &set);
+    encoder.write_text("kala soweli").unwrap();
+    assert!(encoder.write_text(" toki ").is_err());
+    encoder.finish().unwrap();
+    assert_eq!(encoded, [0x23, 0x22]);
+
+    let mut decoded = String::new();
+    Decoder::with_dict_set(&mut decoded, &set)
+        .read_bytes(&encoded)
+        .unwrap();
+    assert_eq!(decoded, "kala soweli");
+}
+
+#[test]
+fn custom_dictionary_set_renders_variations() {
+    use toki_pona_encoding::variation::Variation;
+
+    let dict = Dictionary::from_csv("tp,tp_ZH\nsoweli,牛\nkala,鱼\n");
+    let set = DictionarySet::new(vec![&dict]);
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::with_variation_and_dict_set(&mut encoded, Variation::Hanzi, &set);
+    encoder.write_text("鱼牛").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(encoded, [0x23, 0x21, 0x22]);
+
+    let mut decoded = String::new();
+    Decoder::with_variation_and_dict_set(&mut decoded, Variation::Hanzi, &set)
+        .read_bytes(&encoded)
+        .unwrap();
+    assert_eq!(decoded, "鱼牛");
+
+    let mut decoded = String::new();
+    Decoder::interlinear_with_dict_set(&mut decoded, (Variation::Hanzi, Variation::Default), &set)
+        .read_bytes(&encoded)
+        .unwrap();
+    assert_eq!(decoded, "鱼(kala)牛(soweli)");
+
+    let mut decoded = String::new();
+    Decoder::ruby_with_dict_set(&mut decoded, &set)
+        .read_bytes(&encoded)
+        .unwrap();
+    assert_eq!(
+        decoded,
+        "<ruby>鱼<rt>kala</rt></ruby><ruby>牛<rt>soweli</rt></ruby>"
+    );
+}
+
+#[test]
+fn words_can_start_at_a_later_byte() {
+    use toki_pona_encoding::error::{DecodeError, EncodeError};
+
+    let dict = Dictionary::from_csv("tp\nsoweli\nkala\n");
+    let set = DictionarySet::new(vec![&dict])
+        .with_first_word_byte(0xfe)
+        .unwrap();
+    assert_eq!(set.first_word_byte(), 0xfe);
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::with_dict_set(&mut encoded, &set);
+    encoder.write_text("kala soweli").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(encoded, [0xff, 0xfe]);
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::with_dict_set(&mut decoded, &set);
+    decoder.read_bytes(&encoded).unwrap();
+    assert_eq!(
+        decoder.read_byte(FIRST_WORD_BYTE),
+        Err(DecodeError::UnexpectedByte(FIRST_WORD_BYTE))
+    );
+    assert_eq!(decoded, "kala soweli");
+    assert!(matches!(
+        set.byte_map()[&FIRST_WORD_BYTE],
+        ByteMeaning::Free
+    ));
+
+    // Only as many words fit as there are bytes left.
+    let set = DictionarySet::new(vec![&dict])
+        .with_first_word_byte(0xff)
+        .unwrap();
+    let kala = set.get_identifier("kala").unwrap();
+    assert!(matches!(
+        set.word_to_bytes(kala),
+        Err(EncodeError::DictionaryTooLarge { index: 1 })
+    ));
+}
+
+#[test]
+fn opcodes_can_start_at_a_later_byte() {
+    use toki_pona_encoding::{
+        encoding::{
+            decode_rich_with, diff_with, disassemble_with, instruction_count_with,
+            split_at_boundaries_with, word_histogram_with, InstructionDiff,
+        },
+        error::{DecodeError, DictionaryError},
+    };
+
+    let dict = Dictionary::from_csv("tp\nsoweli\nkala\n");
+    // The words move up to make room for the opcodes.
+    let set = DictionarySet::new(vec![&dict])
+        .with_opcode_base(0x20)
+        .unwrap();
+    assert_eq!(set.opcode_base(), 0x20);
+    assert_eq!(set.first_word_byte(), 0x20 + FIRST_WORD_BYTE);
+    assert_eq!(
+        set.opcode_byte(Opcode::FullStop),
+        0x20 + Opcode::FullStop as u8
+    );
+    assert_eq!(
+        set.opcode(0x20 + Opcode::FullStop as u8),
+        Some(Opcode::FullStop)
+    );
+    assert_eq!(set.opcode(Opcode::FullStop as u8), None);
+    assert!(set
+        .byte_map()
+        .range(..0x20)
+        .all(|(_, meaning)| matches!(meaning, ByteMeaning::Free)));
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::with_dict_set(&mut encoded, &set);
+    encoder.write_text("kala soweli.").unwrap();
+    encoder.finish().unwrap();
+    let soweli = set.first_word_byte();
+    assert_eq!(
+        encoded,
+        [soweli + 1, soweli, set.opcode_byte(Opcode::FullStop)]
+    );
+    assert!(encoded.iter().all(|byte| *byte >= 0x20));
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::with_dict_set(&mut decoded, &set);
+    decoder.read_bytes(&encoded).unwrap();
+    assert_eq!(
+        decoder.read_byte(Opcode::FullStop as u8),
+        Err(DecodeError::UnexpectedByte(Opcode::FullStop as u8))
+    );
+    assert_eq!(decoded, "kala soweli.");
+
+    assert_eq!(
+        disassemble_with(&encoded, &set).unwrap(),
+        ["WORD(kala)", "WORD(soweli)", "PUNCTUATION(.)"]
+    );
+    assert_eq!(instruction_count_with(&encoded, &set), Ok(3));
+    assert_eq!(
+        split_at_boundaries_with(&encoded, 2, &set).unwrap(),
+        [&encoded[..2], &encoded[2..]]
+    );
+    let kala = set.get_identifier("kala").unwrap();
+    assert_eq!(word_histogram_with(&encoded, &set).unwrap()[&kala], 1);
+    assert_eq!(decode_rich_with(&encoded, &set).unwrap()[0].id, kala);
+    assert!(matches!(
+        diff_with(&encoded, &encoded, &set).unwrap()[..],
+        [InstructionDiff::Equal(_)]
+    ));
+
+    // The opcodes and words may not overlap, and there must be room for a word.
+    assert!(matches!(
+        set.with_first_word_byte(FIRST_WORD_BYTE),
+        Err(DictionaryError::ByteRangesOverlap { .. })
+    ));
+    assert!(matches!(
+        DictionarySet::new(vec![&dict]).with_opcode_base(0xff - FIRST_WORD_BYTE + 1),
+        Err(DictionaryError::ByteRangesOverlap { .. })
+    ));
+    assert!(matches!(
+        DictionarySet::new(vec![&dict]).with_first_word_byte(FIRST_WORD_BYTE - 1),
+        Err(DictionaryError::ByteRangesOverlap { .. })
+    ));
+}
+
+#[test]
+fn word_str_gives_the_default_spelling() {
+    for word in ["a", "toki", "pona"] {
+        assert_eq!(
+            DICT_SET.word_str(DICT_SET.get_identifier(word).unwrap()),
+            word
+        );
+    }
+}
+
+#[test]
+fn savings_report_lists_spelling_lengths() {
+    let report = DICT_SET.savings_report();
+    assert_eq!(report.len(), DICT_SET.words().count());
+    let length = |word| {
+        let word = DICT_SET.get_identifier(word).unwrap();
+        report.iter().find(|(w, _)| *w == word).unwrap().1
+    };
+    assert_eq!(length("a"), 1);
+    assert_eq!(length("pona"), 4);
+    assert_eq!(length("sitelen"), 7);
+}
+
+#[test]
+fn word_bytes_round_trip() {
+    for word in DICT_SET.words() {
+        let bytes = DICT_SET.word_to_bytes(word).unwrap();
+        assert_eq!(DICT_SET.word_from_bytes(&bytes), Some(word));
+    }
+    assert_eq!(DICT_SET.word_from_bytes(&[FIRST_WORD_BYTE - 1]), None);
+    assert_eq!(DICT_SET.word_from_bytes(&[]), None);
+}
+
+#[test]
+fn longest_spellings_bound_segmentation() {
+    use toki_pona_encoding::variation::Variation;
+
+    let dict = Dictionary::from_csv("tp\nsoweli\nkala\n");
+    let set = DictionarySet::new(vec![&dict]);
+    assert_eq!(set.longest_spelling(Variation::Default), 6);
+    assert_eq!(set.longest_spelling(Variation::Hanzi), 0);
+    assert_eq!(
+        set.segment("kalasowelikala", Variation::Default)
+            .map(|words| words.len()),
+        Some(3)
+    );
+    #[cfg(feature = "variation-hanzi")]
+    assert_eq!(DICT_SET.longest_spelling(Variation::Hanzi), 1);
+}
+
+#[test]
+fn long_text_segments_without_recursion() {
+    use toki_pona_encoding::variation::Variation;
+
+    let text = "a".repeat(1_000_000);
+    assert_eq!(
+        DICT_SET
+            .segment(&text, Variation::Default)
+            .map(|words| words.len()),
+        Some(1_000_000)
+    );
+    // A longer match is only taken if the rest can still be split.
+    let dict = Dictionary::from_csv("tp\nab\nabc\na\ncd\n");
+    let set = DictionarySet::new(vec![&dict]);
+    let words = set.segment("abcd", Variation::Default).unwrap();
+    let spellings = words
+        .iter()
+        .map(|word| set.word_str(*word))
+        .collect::<Vec<_>>();
+    assert_eq!(spellings, ["ab", "cd"]);
+    assert_eq!(set.segment("abcdb", Variation::Default), None);
+}
+
+#[test]
+fn word_byte_lengths() {
+    // Every word is currently a single byte.
+    assert_eq!(DICT_SET.max_word_bytes(), 1);
+    assert_eq!(DICT_SET.average_word_bytes(), 1.0);
+
+    let empty = DictionarySet::new(Vec::new());
+    assert_eq!(empty.max_word_bytes(), 0);
+    assert_eq!(empty.average_word_bytes(), 0.0);
+}
+
+#[test]
+fn empty_dictionary_set_is_an_error_not_a_panic() {
+    use toki_pona_encoding::error::{DecodeError, EncodeError};
+
+    let empty = DictionarySet::new(Vec::new());
+
+    let mut encoder = Encoder::with_dict_set(Vec::new(), &empty);
+    encoder.write_text("toki").unwrap();
+    assert!(matches!(
+        encoder.finish(),
+        Err(EncodeError::UnknownWord(word)) if word == "toki"
+    ));
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::with_dict_set(&mut decoded, &empty);
+    assert_eq!(
+        decoder.read_byte(FIRST_WORD_BYTE),
+        Err(DecodeError::UnexpectedByte(FIRST_WORD_BYTE))
+    );
+
+    let toki = DICT_SET.get_identifier("toki").unwrap();
+    assert!(matches!(
+        empty.word_to_bytes(toki),
+        Err(EncodeError::UnknownIdentifier(_))
+    ));
+    assert!(empty
+        .byte_map()
+        .values()
+        .all(|meaning| !matches!(meaning, ByteMeaning::Word(_))));
+}
+
+#[test]
+#[cfg(all(
+    feature = "variation-tipunsin",
+    feature = "variation-hanzi",
+    feature = "variation-sitelen"
+))]
+fn variations_for_lists_every_spelling() {
+    use toki_pona_encoding::variation::Variation;
+
+    let telo = DICT_SET.get_identifier("telo").unwrap();
+    assert_eq!(
+        DICT_SET.variations_for(telo),
+        [
+            (Variation::Default, "telo"),
+            (Variation::Tipunsin, "te"),
+            (Variation::Hanzi, "水"),
+            (Variation::SitelenPona, "\u{f196a}"),
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn missing_spellings_are_distinguished_from_fallbacks() {
+    use toki_pona_encoding::variation::Variation;
+
+    let telo = DICT_SET.get_identifier("telo").unwrap();
+    let oko = DICT_SET.get_identifier("oko").unwrap();
+    assert_eq!(
+        DICT_SET.get_word_variation_opt(telo, Variation::Hanzi),
+        Some("水")
+    );
+    assert_eq!(DICT_SET.get_word_variation_opt(oko, Variation::Hanzi), None);
+    assert_eq!(DICT_SET.get_word_variation(oko, Variation::Hanzi), "oko");
+    assert_eq!(
+        DICT_SET.get_word_variation_opt(oko, Variation::Default),
+        Some("oko")
+    );
+}
+
+#[test]
+#[cfg(all(feature = "variation-tipunsin", feature = "variation-hanzi"))]
+fn merged_dictionaries() {
+    use toki_pona_encoding::{error::DictionaryError, variation::Variation};
+
+    let first = Dictionary::from_csv("tp,tp_ZH\nsoweli,牛\nkala,鱼\n");
+    let second = Dictionary::from_csv("tp,tp_S\nkokosila,kok\nsu,\n");
+    let merged = first.merge(&second).unwrap();
+
+    assert_eq!(merged.default.words, ["soweli", "kala", "kokosila", "su"]);
+    assert_eq!(merged.default.lookup["su"], 3);
+    assert_eq!(
+        merged.variations[&Variation::Hanzi].words,
+        [Some("牛"), Some("鱼"), None, None]
+    );
+    assert_eq!(
+        merged.variations[&Variation::Tipunsin].words,
+        [None, None, Some("kok"), None]
+    );
+    assert_eq!(merged.variations[&Variation::Tipunsin].lookup["kok"], 2);
+
+    // The merged dictionary encodes like any other.
+    let set = DictionarySet::new(vec![&merged]);
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::with_dict_set(&mut encoded, &set);
+    encoder.write_text("kala kokosila").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(encoded, [0x23, 0x24]);
+
+    let duplicate = Dictionary::from_csv("tp,tp_ZH\nkili,鱼\n");
+    assert_eq!(
+        first.merge(&duplicate).unwrap_err(),
+        DictionaryError::DuplicateWord {
+            word: "鱼".to_owned(),
+            variation: Variation::Hanzi
+        }
+    );
+    assert!(matches!(
+        first.merge(&first),
+        Err(DictionaryError::DuplicateWord {
+            variation: Variation::Default,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn malformed_csv_is_an_error() {
+    use toki_pona_encoding::error::DictionaryError;
+
+    assert_eq!(
+        Dictionary::try_from_csv("tp,tp_ZH\nsoweli,牛\nkala\nkili,果\n").unwrap_err(),
+        DictionaryError::WrongColumnCount {
+            line: 3,
+            expected: 2,
+            found: 1
+        }
+    );
+    assert_eq!(
+        Dictionary::try_from_csv("tp,tp_ZH\nsoweli,牛,鱼\n").unwrap_err(),
+        DictionaryError::WrongColumnCount {
+            line: 2,
+            expected: 2,
+            found: 3
+        }
+    );
+    assert_eq!(
+        Dictionary::try_from_csv("").unwrap_err(),
+        DictionaryError::MissingHeaders
+    );
+    assert_eq!(
+        Dictionary::try_from_csv("tp_ZH,tp\n").unwrap_err(),
+        DictionaryError::MissingDefaultColumn
+    );
+    assert_eq!(
+        Dictionary::try_from_csv("tp,tp_XX\n").unwrap_err(),
+        DictionaryError::UnknownVariation {
+            name: "tp_XX".to_owned()
+        }
+    );
+    assert_eq!(
+        Dictionary::try_from_csv("tp,tp_S\nsoweli,sow\nKala,kaw\n").unwrap_err(),
+        DictionaryError::UpperCaseWord { line: 3 }
+    );
+    // Empty variation cells still count as columns.
+    assert!(Dictionary::try_from_csv("tp,tp_ZH,tp_S\nsoweli,,\n").is_ok());
+    assert!(matches!(
+        Dictionary::try_from_csv("tp\nsoweli\nkala\nsoweli\n").unwrap_err(),
+        DictionaryError::DuplicateWord { word, .. } if word == "soweli"
+    ));
+}
+
+#[test]
+fn tab_separated_dictionaries_parse_like_csv() {
+    use toki_pona_encoding::error::DictionaryError;
+
+    let csv = Dictionary::from_csv("tp,tp_ZH\nsoweli,牛\nkala,鱼\n");
+    let tsv = Dictionary::from_delimited("tp\ttp_ZH\nsoweli\t牛\nkala\t鱼\n", '\t');
+    assert_eq!(tsv.default.words, csv.default.words);
+    assert_eq!(tsv.variations.len(), csv.variations.len());
+
+    // Commas need no escaping in a tab-separated file.
+    let commas = Dictionary::from_delimited("tp\ttp_S\nsoweli\tso,we\n", '\t');
+    assert_eq!(commas.default.words, ["soweli"]);
+    assert_eq!(
+        Dictionary::try_from_delimited("tp\ttp_ZH\nsoweli\n", '\t').unwrap_err(),
+        DictionaryError::WrongColumnCount {
+            line: 2,
+            expected: 2,
+            found: 1
+        }
+    );
+}
+
+#[test]
+fn lookup_tables_must_match_words() {
+    for dict in toki_pona_encoding::dict::default_dictionaries() {
+        assert!(dict.is_consistent());
+    }
+
+    let mut dict = Dictionary::from_csv("tp\nsoweli\nkala\n");
+    assert!(dict.is_consistent());
+    dict.default.lookup.insert("kala", 0);
+    assert!(!dict.is_consistent());
+}
+
+#[test]
+fn dictionaries_can_be_shared_between_threads() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Dictionary<'static>>();
+    assert_send_sync::<DictionarySet<'static>>();
+
+    let encoded = std::thread::scope(|scope| {
+        let threads = ["toki pona", "mi moku"].map(|text| {
+            scope.spawn(move || {
+                let mut encoded = Vec::new();
+                let mut encoder = Encoder::with_dict_set(&mut encoded, &DICT_SET);
+                encoder.write_text(text).unwrap();
+                encoder.finish().unwrap();
+                encoded
+            })
+        });
+        threads.map(|thread| thread.join().unwrap())
+    });
+    assert_eq!(encoded[0].len(), 2);
+    assert_eq!(encoded[1].len(), 2);
+}
@@ -0,0 +1,29 @@
+//! Byte strings produced by the original single-byte encoder.
+//! These must continue to decode identically: any change to the byte assignments of
+//! `AttachToPrevious` (0x21) or the single-byte words (0x22 upwards) breaks existing data.
+
+use toki_pona_encoding::encoding::{decode, encode};
+
+const LEGACY: &[(&[u8], &str)] = &[
+    (&[0x25, 0x23, 0x22, 0x51, 0x69], "alasa akesi a lukin oko"),
+    (&[0x91, 0x79], "toki pona"),
+    (&[0x57, 0x59, 0x2b, 0x3c], "mi moku e kili"),
+    (&[0x33, 0x4a, 0x79], "jan li pona"),
+    (&[0x22, 0x22, 0x22], "a a a"),
+    (&[0x9c], "wile"),
+    (&[0x91, 0x21, 0x79, 0x22], "tokipona a"),
+];
+
+#[test]
+fn legacy_streams_decode_identically() {
+    for (bytes, text) in LEGACY {
+        assert_eq!(decode(bytes).unwrap(), *text);
+    }
+}
+
+#[test]
+fn legacy_streams_are_still_produced() {
+    for (bytes, text) in LEGACY {
+        assert_eq!(encode(text).unwrap(), *bytes);
+    }
+}
@@ -0,0 +1,2202 @@
+use std::borrow::Cow;
+
+use toki_pona_encoding::{
+    dict_set::DICT_SET,
+    encoding::{decode_into, decode_stream, disassemble, Decoder, Encoder, Opcode},
+    error::{DecodeError, EncodeError},
+};
+
+fn encode(text: &str) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.write_text(text).unwrap();
+    encoder.finish().unwrap();
+    encoded
+}
+
+fn decode(bytes: &[u8]) -> String {
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.read_bytes(bytes).unwrap();
+    decoded
+}
+
+fn round_trip(text: &str) -> String {
+    decode(&encode(text))
+}
+
+#[test]
+fn demo_round_trips() {
+    let text = "alasa akesi a lukin oko";
+    assert_eq!(round_trip(text), text);
+}
+
+#[test]
+fn char_iterators_round_trip() {
+    let text = "mi moku e kili, tokipona li pona.";
+    // Each split is partway through a word, or just before or after the space between two.
+    for split in [4, 6, 7, 8, 20] {
+        let (first, second) = text.split_at(split);
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.write_chars(first.chars()).unwrap();
+        encoder
+            .write_chars(second.chars().collect::<Vec<_>>().into_iter())
+            .unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(encoded, encode(text));
+        assert_eq!(decode(&encoded), text);
+    }
+}
+
+#[test]
+fn single_word_round_trips() {
+    let toki = DICT_SET.get_identifier("toki").unwrap();
+    let encoded = encode("toki");
+    assert_eq!(encoded, DICT_SET.word_to_bytes(toki).unwrap());
+    assert_eq!(decode(&encoded), "toki");
+
+    // Dropping the encoder also encodes the last word.
+    let mut dropped = Vec::new();
+    Encoder::new(&mut dropped).write_text("toki").unwrap();
+    assert_eq!(dropped, encoded);
+}
+
+#[test]
+fn particles_use_implicit_spaces() {
+    for text in [
+        "a",
+        "a a a",
+        "jan li pona",
+        "mi moku e kili",
+        "o awen",
+        "jan pi ma tomo en jan ali la",
+    ] {
+        let encoded = encode(text);
+        assert_eq!(
+            encoded.len(),
+            text.split(' ').count(),
+            "expected one byte per word for {:?}",
+            text
+        );
+        assert!(
+            !encoded.contains(&0x21),
+            "unexpected attachment in {:?}",
+            text
+        );
+        assert_eq!(decode(&encoded), text);
+    }
+}
+
+#[test]
+fn repeated_particle_is_the_same_byte() {
+    let encoded = encode("a a a");
+    assert_eq!(encoded, vec![encoded[0]; 3]);
+}
+
+#[test]
+#[cfg(all(feature = "variation-hanzi", feature = "variation-sitelen"))]
+fn transcode_renders_in_target_variation() {
+    use toki_pona_encoding::{encoding::transcode, variation::Variation};
+
+    let encoded = encode("alasa akesi a lukin oko");
+    assert_eq!(
+        transcode(&encoded, Variation::Hanzi).unwrap(),
+        "搜 龟 啊 见 oko"
+    );
+    assert_eq!(
+        transcode(&encoded, Variation::Default).unwrap(),
+        "alasa akesi a lukin oko"
+    );
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn set_variation_round_trips() {
+    use toki_pona_encoding::{encoding::transcode, variation::Variation};
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.write_text("toki").unwrap();
+    encoder.set_variation(Variation::Hanzi).unwrap();
+    encoder.write_text(" 好").unwrap();
+    encoder.finish().unwrap();
+
+    assert_eq!(decode(&encoded), "toki 好");
+    // The requested variation takes precedence over the one in the stream.
+    assert_eq!(
+        transcode(&encoded, Variation::Default).unwrap(),
+        "toki pona"
+    );
+}
+
+#[test]
+fn punctuation_attaches_to_previous_word() {
+    assert_eq!(round_trip("toki. pona"), "toki. pona");
+    assert_eq!(round_trip("toki, pona: mi"), "toki, pona: mi");
+    assert_eq!(round_trip("toki.pona"), "toki.pona");
+    assert_eq!(round_trip("toki pona."), "toki pona.");
+    assert_eq!(encode("toki. pona").len(), 3);
+}
+
+#[test]
+fn longest_match_is_preferred() {
+    // `mama` is preferred over `ma` followed by `ma`.
+    assert_eq!(encode("mama").len(), 1);
+    assert_eq!(encode("mamamani").len(), 3);
+    assert_eq!(round_trip("mamamani"), "mamamani");
+}
+
+#[test]
+fn shorter_match_is_used_when_longer_match_dead_ends() {
+    // `sinpin` matches first, but `i` is not a word, so `sin` and `pini` are used instead.
+    let encoded = encode("sinpini");
+    assert_eq!(encoded.len(), 3);
+    assert_eq!(encoded[1], 0x21);
+    assert_eq!(round_trip("sinpini"), "sinpini");
+    assert_eq!(round_trip("mi sinpini lon"), "mi sinpini lon");
+}
+
+#[test]
+fn unsplittable_text_is_an_error() {
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    assert!(encoder.write_text("sinpinx").is_ok());
+    assert!(matches!(
+        encoder.finish(),
+        Err(EncodeError::UnknownWord(word)) if word == "sinpinx"
+    ));
+}
+
+#[test]
+fn decode_into_reports_full_buffer() {
+    let encoded = encode("toki pona");
+    let mut out = [0; 16];
+    assert_eq!(decode_into(&encoded, &mut out), Ok(9));
+    assert_eq!(&out[..9], b"toki pona");
+
+    let mut out = [0; 6];
+    assert_eq!(
+        decode_into(&encoded, &mut out),
+        Err(DecodeError::BufferFull { written: 6 })
+    );
+    assert_eq!(&out, b"toki p");
+
+    let mut out = [0; 16];
+    assert_eq!(
+        decode_into(&[Opcode::RawText as u8, 5, b'a'], &mut out),
+        Err(DecodeError::TruncatedPayload {
+            claimed: 5,
+            remaining: 1
+        })
+    );
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn decode_into_does_not_split_characters() {
+    use toki_pona_encoding::variation::Variation;
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.set_variation(Variation::Hanzi).unwrap();
+    encoder.write_text("言 好").unwrap();
+    encoder.finish().unwrap();
+
+    // Each hanzi character is three bytes long, so the second one does not fit.
+    let mut out = [0; 5];
+    assert_eq!(
+        decode_into(&encoded, &mut out),
+        Err(DecodeError::BufferFull { written: 4 })
+    );
+    assert_eq!(&out[..4], "言 ".as_bytes());
+}
+
+#[test]
+fn unexpected_bytes_are_errors() {
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    assert_eq!(
+        decoder.read_byte(0x01),
+        Err(DecodeError::UnexpectedByte(0x01))
+    );
+    assert_eq!(
+        decoder.read_byte(0xff),
+        Err(DecodeError::UnexpectedByte(0xff))
+    );
+    assert_eq!(
+        decoder.read_bytes(&[0x20, 0x7f]),
+        Err(DecodeError::UnknownVariation(0x7f))
+    );
+}
+
+#[test]
+fn unknown_bytes_can_be_skipped_or_shown() {
+    use toki_pona_encoding::encoding::UnknownBytes;
+
+    let mut bytes = encode("mi pona");
+    bytes.insert(1, 0x01);
+    for (unknown_bytes, expected) in [
+        (UnknownBytes::Skip, "mi pona"),
+        (UnknownBytes::Placeholder, "mi \u{fffd} pona"),
+    ] {
+        let mut decoded = String::new();
+        let mut decoder = Decoder::new(&mut decoded);
+        decoder.unknown_bytes(unknown_bytes);
+        decoder.read_bytes(&bytes).unwrap();
+        assert_eq!(decoder.position(), bytes.len());
+        assert_eq!(decoded, expected);
+
+        let mut decoded = String::new();
+        let mut decoder = Decoder::new(&mut decoded);
+        decoder.unknown_bytes(unknown_bytes);
+        for byte in &bytes {
+            decoder.read_byte(*byte).unwrap();
+        }
+        assert_eq!(decoded, expected);
+    }
+}
+
+#[test]
+fn instructions_can_be_edited_and_reencoded() {
+    use toki_pona_encoding::{
+        dict_set::DICT_SET,
+        encoding::{decode_to_instructions, encode_instructions, Instruction},
+    };
+
+    let encoded = encode("mi moku, kili \u{1f34e} li pona :)");
+    let mut instructions = decode_to_instructions(&encoded).unwrap();
+    assert_eq!(encode_instructions(&instructions).unwrap(), encoded);
+
+    let index = instructions
+        .iter()
+        .position(|instruction| matches!(instruction, Instruction::RawText { .. }))
+        .unwrap();
+    instructions[index] = Instruction::TokiPonaWord {
+        word: DICT_SET.get_identifier("suwi").unwrap(),
+    };
+    assert_eq!(
+        decode(&encode_instructions(&instructions).unwrap()),
+        "mi moku, kili suwi li pona :)"
+    );
+
+    // Codes outside the phrase and abbreviation tables would not decode.
+    assert!(matches!(
+        encode_instructions(&[Instruction::Phrase { code: 0xff }]),
+        Err(EncodeError::UnknownPhrase(0xff))
+    ));
+    assert!(matches!(
+        encode_instructions(&[Instruction::Abbreviation { code: 0xff }]),
+        Err(EncodeError::UnknownAbbreviation(0xff))
+    ));
+}
+
+#[test]
+fn finish_reports_cut_off_streams() {
+    let encoded = encode("tokipona li pona");
+    let attach = encoded
+        .iter()
+        .position(|&b| b == Opcode::AttachToPrevious as u8)
+        .unwrap();
+    for (bytes, expected) in [
+        (&encoded[..], Ok(())),
+        (&encoded[..attach + 1], Err(DecodeError::TruncatedStream)),
+        (
+            &[Opcode::RawText as u8, 3, b'a'][..],
+            Err(DecodeError::TruncatedPayload {
+                claimed: 3,
+                remaining: 1,
+            }),
+        ),
+        (&[][..], Ok(())),
+    ] {
+        let mut decoded = String::new();
+        let mut decoder = Decoder::new(&mut decoded);
+        decoder.read_bytes(bytes).unwrap();
+        assert_eq!(decoder.finish(), expected);
+
+        let mut decoded = String::new();
+        let mut decoder = Decoder::new(&mut decoded);
+        for byte in bytes {
+            decoder.read_byte(*byte).unwrap();
+        }
+        assert_eq!(decoder.finish(), expected);
+    }
+}
+
+#[test]
+fn raw_text_must_be_valid_utf8() {
+    let mut bytes = encode("mi");
+    bytes.extend([Opcode::RawText as u8, 2, 0xc3, 0x28]);
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    assert_eq!(decoder.read_bytes(&bytes), Err(DecodeError::InvalidUtf8));
+    assert_eq!(decoder.position(), bytes.len());
+    assert_eq!(decoded, "mi");
+}
+
+#[test]
+fn apostrophes_are_errors_unless_normalized() {
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    assert!(matches!(
+        encoder.write_text("a' toki"),
+        Err(EncodeError::UnknownWord(word)) if word == "a'"
+    ));
+    drop(encoder);
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.normalize_diacritics(true);
+    encoder.write_text("a' tóki pona\u{301}").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(decode(&encoded), "a toki pona");
+}
+
+#[test]
+fn decode_stream_yields_each_piece() {
+    let encoded = encode("toki pona. mi");
+    let pieces = decode_stream(&encoded)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(pieces, ["toki", " ", "pona", ".", " ", "mi"]);
+    assert!(pieces.iter().all(|piece| matches!(piece, Cow::Borrowed(_))));
+
+    let mut stream = decode_stream(&[0x22, 0x20]);
+    assert_eq!(stream.next(), Some(Ok(Cow::Borrowed("a"))));
+    assert_eq!(stream.next(), Some(Err(DecodeError::TruncatedStream)));
+    assert_eq!(stream.next(), None);
+}
+
+/// A writer that fails once it has been given a certain number of pieces of text.
+struct LimitedWriter {
+    text: String,
+    remaining: usize,
+}
+
+impl std::fmt::Write for LimitedWriter {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        if self.remaining == 0 {
+            return Err(std::fmt::Error);
+        }
+        self.remaining -= 1;
+        self.text.push_str(s);
+        Ok(())
+    }
+}
+
+#[test]
+fn writer_errors_are_returned() {
+    let mut writer = LimitedWriter {
+        text: String::new(),
+        remaining: 3,
+    };
+    let mut decoder = Decoder::new(&mut writer);
+    // Each word is written separately when reading byte by byte.
+    assert_eq!(
+        encode("toki pona mi")
+            .into_iter()
+            .try_for_each(|byte| decoder.read_byte(byte)),
+        Err(DecodeError::Write(std::fmt::Error))
+    );
+    assert_eq!(writer.text, "toki pona");
+
+    // All of the text from one call to read_bytes is written at once.
+    let mut writer = LimitedWriter {
+        text: String::new(),
+        remaining: 1,
+    };
+    let mut decoder = Decoder::new(&mut writer);
+    assert_eq!(decoder.read_bytes(&encode("toki pona")), Ok(()));
+    assert_eq!(
+        decoder.read_bytes(&encode("mi")),
+        Err(DecodeError::Write(std::fmt::Error))
+    );
+    assert_eq!(writer.text, "toki pona");
+}
+
+#[test]
+fn particles_mixed_with_punctuation() {
+    for text in [
+        "mi pona. taso sina seme",
+        "ona li pona kin.",
+        "taso, mi wile e ni: sina pona kin",
+        "mi pona.taso sina seme",
+        "kin, taso.",
+    ] {
+        assert_eq!(round_trip(text), text);
+    }
+    // `taso` after a full stop needs no attachment, so is a single byte.
+    assert_eq!(encode("pona. taso").len(), 3);
+    assert_eq!(encode("pona.taso")[2], 0x21);
+}
+
+#[test]
+fn question_and_exclamation_marks() {
+    for text in [
+        "sina pona anu seme?",
+        "sina pona anu seme? mi pona!",
+        "mi pona. taso sina seme?",
+        "a!",
+    ] {
+        assert_eq!(round_trip(text), text);
+    }
+    assert_eq!(
+        encode("seme?"),
+        [encode("seme")[0], Opcode::QuestionMark as u8]
+    );
+}
+
+#[test]
+#[cfg(feature = "variation-sitelen")]
+fn interlinear_sitelen_pona() {
+    use toki_pona_encoding::{encoding::transcode, variation::Variation};
+
+    let encoded = encode("mi wile moku.");
+    let mut decoded = String::new();
+    Decoder::interlinear(&mut decoded, (Variation::SitelenPona, Variation::Default))
+        .read_bytes(&encoded)
+        .unwrap();
+    assert_eq!(decoded, "\u{f1934}(mi) \u{f1977}(wile) \u{f1936}(moku).");
+    assert_eq!(
+        transcode(&encode("toki pona"), Variation::SitelenPona).unwrap(),
+        "\u{f196c} \u{f1954}"
+    );
+}
+
+#[test]
+fn emoji_are_raw_text() {
+    for text in [
+        "pona 👍 tawa sina",
+        "pona👍 tawa sina",
+        "mi olin e sina ❤️",
+        "👋🏽 jan",
+        "🇬🇧🇫🇷 toki",
+        "kulupu 👨‍👩‍👧",
+    ] {
+        assert_eq!(round_trip(text), text);
+    }
+
+    let encoded = encode("pona👍");
+    assert_eq!(encoded[1..3], [0x21, Opcode::RawText as u8]);
+}
+
+#[test]
+fn word_separators_act_as_spaces() {
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.word_separators(&['\u{b7}']);
+    encoder.write_text("mi\u{b7}moku\u{b7}e kili").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(encoded, encode("mi moku e kili"));
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    assert!(encoder.write_text("mi\u{b7}moku ").is_err());
+}
+
+#[test]
+fn debug_layout_spells_out_words() {
+    use toki_pona_encoding::encoding::Layout;
+
+    let text = "mi moku, sina pona.";
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.layout(Layout::Debug);
+    encoder.write_text(text).unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(&encoded[..4], [Opcode::SpeltWord as u8, 2, b'm', b'i']);
+    assert!(encoded.windows(4).any(|window| window == b"moku"));
+    assert_eq!(decode(&encoded), text);
+
+    let mut decoded = String::new();
+    assert_eq!(
+        Decoder::new(&mut decoded).read_bytes(&[Opcode::SpeltWord as u8, 3, b'x', b'y', b'z']),
+        Err(DecodeError::UnknownSpelling("xyz".to_owned()))
+    );
+}
+
+#[test]
+fn phrases_are_encoded_as_one_instruction() {
+    use toki_pona_encoding::encoding::word_histogram;
+
+    let encode_phrases = |text: &str| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.phrases(true).sentence_case(true);
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+        encoded
+    };
+    for text in [
+        "tenpo ni la mi moku",
+        "Tenpo ni la mi pilin pona.",
+        "mi wile e ni: tenpo kama la sina pona anu seme?",
+        "tenpo ni, la mi",
+        "tenpotenpo ni la",
+        "tenpo ni",
+    ] {
+        assert_eq!(decode(&encode_phrases(text)), text);
+    }
+
+    // The longest phrase is preferred.
+    let encoded = encode_phrases("tenpo ni la mi moku");
+    assert_eq!(
+        disassemble(&encoded).unwrap(),
+        ["PHRASE(tenpo ni la mi)", "WORD(moku)"]
+    );
+    assert_eq!(encoded.len(), 3);
+    assert_eq!(word_histogram(&encoded).unwrap().values().sum::<usize>(), 5);
+}
+
+#[test]
+fn clinging_punctuation_is_stripped_from_words() {
+    for text in [
+        "(toki, pona)",
+        "jan li toki e ni: \"mi pona\"",
+        "\u{201c}toki!\u{201d} ona li toki",
+        "\u{ab}pona\u{bb}",
+        "mi [kepeken] e ni",
+        "toki - pona",
+    ] {
+        assert_eq!(round_trip(text), text);
+    }
+
+    // The opening bracket is raw text attached to the word after it, the comma has its own
+    // instruction, and the closing bracket is punctuation attached to the word before it.
+    assert_eq!(
+        disassemble(&encode("[toki, pona]")).unwrap(),
+        [
+            "RAW_TEXT(\"[\")",
+            "ATTACH",
+            "WORD(toki)",
+            "PUNCTUATION(,)",
+            "WORD(pona)",
+            "PUNCTUATION_RUN(\"]\")"
+        ]
+    );
+}
+
+#[test]
+fn round_brackets_are_groups() {
+    for text in [
+        "jan (pi toki pona) li pona",
+        "jan (pi (toki pona)) li pona",
+        "(mi) (sina)",
+        "(Mi)",
+        "( mi )",
+        "mi (tokipona).",
+    ] {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.preserve_case(true);
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(decode(&encoded), text);
+    }
+
+    // Each bracket takes a single byte.
+    assert_eq!(
+        disassemble(&encode("jan (pi toki pona) li pona")).unwrap(),
+        [
+            "WORD(jan)",
+            "GROUP_OPEN",
+            "WORD(pi)",
+            "WORD(toki)",
+            "WORD(pona)",
+            "GROUP_CLOSE",
+            "WORD(li)",
+            "WORD(pona)"
+        ]
+    );
+}
+
+#[test]
+fn punctuation_runs_attach_to_previous_word() {
+    for text in [
+        "mi pona :)",
+        "mi olin e sina:-)",
+        "seme?!",
+        "ni li pona...",
+        "toki,pona",
+        "toki ,pona",
+        "mi moku;) sina moku",
+        "mi jo e ni: \"ijo\".",
+    ] {
+        assert_eq!(round_trip(text), text);
+    }
+
+    assert_eq!(
+        disassemble(&encode("pona^_^ ...")).unwrap(),
+        [
+            "WORD(pona)",
+            "PUNCTUATION_RUN(\"^_^\")",
+            "RAW_TEXT(\"...\")"
+        ]
+    );
+    // Marks with their own instructions are a byte each, which is shorter than a run.
+    assert_eq!(
+        encode("pona..."),
+        [
+            encode("pona")[0],
+            Opcode::FullStop as u8,
+            Opcode::FullStop as u8,
+            Opcode::FullStop as u8
+        ]
+    );
+}
+
+#[test]
+fn long_runs_of_words_are_not_split_inside_a_word() {
+    for text in [
+        "tokiponatokiponatokiponatokipona",
+        "mi sitelensitelenpona",
+        "kalamakalamakalamapimeja li pona",
+    ] {
+        assert_eq!(round_trip(text), text);
+
+        // Writing one character at a time gives the same bytes.
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        for c in text.chars() {
+            encoder.write_character(c).unwrap();
+        }
+        encoder.finish().unwrap();
+        assert_eq!(encoded, encode(text));
+    }
+}
+
+#[test]
+fn unknown_text_can_fall_back_to_raw_text() {
+    assert_eq!(
+        disassemble(&encode("#tokipona")).unwrap(),
+        [
+            "RAW_TEXT(\"#\")",
+            "ATTACH",
+            "WORD(toki)",
+            "ATTACH",
+            "WORD(pona)"
+        ]
+    );
+
+    let text = "mi tawa https://example.com/tokipona, jan Xyz li pona";
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.raw_text_fallback(true);
+    encoder.write_text(text).unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(decode(&encoded), text);
+    assert!(disassemble(&encoded)
+        .unwrap()
+        .contains(&"RAW_TEXT(\"Xyz\")".to_owned()));
+}
+
+#[test]
+#[cfg(feature = "graphemes")]
+fn long_grapheme_clusters_are_not_split() {
+    // This emoji is a single grapheme cluster of eleven code points,
+    // and here it straddles the point at which the encoder starts splitting long tokens.
+    let family = "👨🏽‍👩🏽‍👧🏽‍👦🏽";
+    let text = format!("ponaponapona{}", family);
+    assert_eq!(round_trip(&text), text);
+    let bytes = encode(&text);
+    let raw_text = bytes.iter().position(|&b| b == 0x1a).unwrap();
+    assert_eq!(bytes[raw_text + 1] as usize, family.len());
+    assert_eq!(&bytes[raw_text + 2..], family.as_bytes());
+}
+
+#[test]
+fn encoding_stops_within_a_budget() {
+    use toki_pona_encoding::encoding::encode_budget;
+
+    let text = "mi moku e kili \u{1f34e} lon tomo.";
+    assert_eq!(encode_budget(text, 2).unwrap(), (encode("mi moku"), 7));
+    // The emoji takes six bytes, so does not fit in the next five.
+    assert_eq!(
+        encode_budget(text, 9).unwrap(),
+        (encode("mi moku e kili"), 14)
+    );
+    assert_eq!(encode_budget(text, 0).unwrap(), (Vec::new(), 0));
+    let all = encode(text);
+    assert_eq!(
+        encode_budget(text, all.len()).unwrap(),
+        (all, text.chars().count())
+    );
+    assert_eq!(
+        encode_budget("\u{1f34e} pona", 7).unwrap(),
+        (encode("\u{1f34e} pona"), 6)
+    );
+    // A word that cannot be encoded is an error once it is reached, giving where it starts.
+    assert!(matches!(
+        encode_budget("mi xqz moku", 10),
+        Err(EncodeError::At { offset: 3, .. })
+    ));
+    assert!(matches!(
+        encode_budget("xqz mi", 10),
+        Err(EncodeError::At { offset: 0, .. })
+    ));
+}
+
+#[test]
+fn budgets_can_mark_cut_off_text() {
+    use toki_pona_encoding::encoding::encode_budget_marked;
+
+    let text = "mi moku e kili lon tomo sina li pona";
+    // The marker takes five bytes, so only two words fit with it in seven.
+    assert_eq!(encode("mi moku …").len(), 7);
+    assert_eq!(
+        encode_budget_marked(text, 7, "…").unwrap(),
+        (encode("mi moku …"), 7)
+    );
+    assert_eq!(
+        encode_budget_marked(text, 8, "…").unwrap(),
+        (encode("mi moku e …"), 9)
+    );
+    // Text that fits whole is not marked.
+    assert_eq!(
+        encode_budget_marked(text, 100, "…").unwrap(),
+        (encode(text), text.len())
+    );
+    assert_eq!(encode_budget_marked(text, 4, "…").unwrap(), (Vec::new(), 0));
+}
+
+#[test]
+fn only_the_encoder_output_is_canonical() {
+    use toki_pona_encoding::encoding::is_canonical;
+
+    for text in ["mi moku, sina pona.", "tokipona", "(toki) \u{1f44d}"] {
+        assert!(is_canonical(&encode(text)).unwrap());
+    }
+    // An attach before punctuation is redundant.
+    let mut padded = encode("toki");
+    padded.extend([Opcode::AttachToPrevious as u8, Opcode::FullStop as u8]);
+    assert_eq!(decode(&padded), "toki.");
+    assert!(!is_canonical(&padded).unwrap());
+    // A spelt word decodes the same as its single byte.
+    let spelt = [Opcode::SpeltWord as u8, 4, b't', b'o', b'k', b'i'];
+    assert!(!is_canonical(&spelt).unwrap());
+    assert_eq!(
+        is_canonical(&[Opcode::RawText as u8, 4]),
+        Err(DecodeError::TruncatedPayload {
+            claimed: 4,
+            remaining: 0
+        })
+    );
+}
+
+#[test]
+fn normalize_is_the_decoded_encoding() {
+    use toki_pona_encoding::encoding::normalize;
+
+    for text in ["mi moku, sina pona.", "tokipona", "(toki) \u{1f44d}"] {
+        assert_eq!(normalize(text).unwrap(), text);
+    }
+    assert!(matches!(
+        normalize("toki  pona"),
+        Err(EncodeError::UnknownWord(word)) if word.is_empty()
+    ));
+}
+
+#[test]
+fn split_at_boundaries_keeps_instructions_whole() {
+    use toki_pona_encoding::encoding::split_at_boundaries;
+
+    let text = "mi \u{1f44d} tawa sina. toki pona li pona";
+    let encoded = encode(text);
+    let emoji = encoded
+        .iter()
+        .position(|&b| b == Opcode::RawText as u8)
+        .unwrap();
+    let chunks = split_at_boundaries(&encoded, 6).unwrap();
+    assert!(chunks.iter().all(|chunk| chunk.len() <= 6));
+    assert_eq!(chunks.concat(), encoded);
+    // The raw text instruction is six bytes, so it fills a chunk of its own.
+    assert!(chunks.contains(&&encoded[emoji..emoji + 6]));
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    for chunk in chunks {
+        decoder.read_bytes(chunk).unwrap();
+    }
+    assert_eq!(decoded, text);
+
+    assert_eq!(
+        split_at_boundaries(&encoded, 5),
+        Err(DecodeError::InstructionTooLong { len: 6 })
+    );
+    assert_eq!(split_at_boundaries(&[], 5), Ok(Vec::new()));
+}
+
+#[test]
+fn validate_all_reports_every_unknown_token() {
+    use toki_pona_encoding::{encoding::validate_all, error::EncodeError};
+
+    assert!(validate_all("mi moku. sina pona").is_empty());
+
+    let text = "mi xyz li pona. abc jan";
+    let errors = validate_all(text)
+        .into_iter()
+        .map(|err| match err {
+            EncodeError::At { offset, error } => match *error {
+                EncodeError::UnknownWord(word) => (offset, word),
+                other => panic!("unexpected error {:?}", other),
+            },
+            other => panic!("unexpected error {:?}", other),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(errors, vec![(3, "xyz".to_owned()), (16, "abc".to_owned())]);
+    assert_eq!(&text[16..19], "abc");
+}
+
+#[test]
+fn instructions_are_counted() {
+    use toki_pona_encoding::encoding::instruction_count;
+
+    assert_eq!(instruction_count(&[]), Ok(0));
+    assert_eq!(instruction_count(&encode("toki pona")), Ok(2));
+    // A spelt word and its length are one instruction.
+    let spelt = [Opcode::SpeltWord as u8, 4, b't', b'o', b'k', b'i'];
+    assert_eq!(instruction_count(&spelt), Ok(1));
+    assert_eq!(instruction_count(&encode("tokipona.")), Ok(4));
+    assert_eq!(
+        instruction_count(&spelt[..3]),
+        Err(DecodeError::TruncatedPayload {
+            claimed: 4,
+            remaining: 1
+        })
+    );
+}
+
+#[test]
+fn hex_round_trip() {
+    use toki_pona_encoding::encoding::{decode_hex, encode_hex};
+
+    for text in ["", "mi moku. \u{1f431}", "toki, sina pona!"] {
+        let hex = encode_hex(text).unwrap();
+        assert!(hex.chars().all(|c| matches!(c, '0'..='9' | 'a'..='f')));
+        assert_eq!(decode_hex(&hex).unwrap(), text);
+        assert_eq!(decode_hex(&hex.to_uppercase()).unwrap(), text);
+    }
+    assert_eq!(encode_hex("mi moku seme").unwrap(), "57597e");
+    assert_eq!(
+        decode_hex("5759x7"),
+        Err(DecodeError::InvalidHex { offset: 4 })
+    );
+    assert_eq!(
+        decode_hex("57597"),
+        Err(DecodeError::InvalidHex { offset: 4 })
+    );
+}
+
+#[test]
+fn base64_round_trip() {
+    use toki_pona_encoding::{
+        encoding::{decode_base64, decode_base64_url, encode_base64, encode_base64_url},
+        error::DecodeError,
+    };
+
+    // The empty string and streams of every length modulo three.
+    for text in ["", "mi", "mi moku", "mi moku. 🐱", "toki, sina pona!"] {
+        let standard = encode_base64(text).unwrap();
+        assert_eq!(standard.len() % 4, 0);
+        assert_eq!(decode_base64(&standard).unwrap(), text);
+        assert_eq!(decode_base64(standard.trim_end_matches('=')).unwrap(), text);
+
+        let url = encode_base64_url(text).unwrap();
+        assert!(url
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(decode_base64_url(&url).unwrap(), text);
+    }
+
+    // The bytes 0x57, 0x59, 0x7e, which differ between the two alphabets.
+    assert_eq!(encode_base64("mi moku seme").unwrap(), "V1l+");
+    assert_eq!(encode_base64_url("mi moku seme").unwrap(), "V1l-");
+    assert_eq!(encode_base64("toki pona li pona").unwrap(), "kXlKeQ==");
+    assert_eq!(encode_base64_url("toki pona li pona").unwrap(), "kXlKeQ");
+
+    assert_eq!(
+        decode_base64("V1l-"),
+        Err(DecodeError::InvalidBase64 { offset: 3 })
+    );
+    assert_eq!(
+        decode_base64("kXlKe"),
+        Err(DecodeError::InvalidBase64 { offset: 4 })
+    );
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn disassemble_names_each_instruction() {
+    use toki_pona_encoding::variation::Variation;
+
+    let mut bytes = Vec::new();
+    let mut encoder = Encoder::new(&mut bytes);
+    encoder.write_text("tokipona, 🐱").unwrap();
+    encoder.set_variation(Variation::Hanzi).unwrap();
+    encoder.write_text(" 言").unwrap();
+    encoder.finish().unwrap();
+
+    assert_eq!(
+        disassemble(&bytes).unwrap(),
+        [
+            "WORD(toki)",
+            "ATTACH",
+            "WORD(pona)",
+            "PUNCTUATION(,)",
+            "RAW_TEXT(\"🐱\")",
+            "SET_VARIATION(tp_ZH)",
+            "WORD(toki)",
+        ]
+    );
+}
+
+#[test]
+fn chunk_boundaries_are_not_token_boundaries() {
+    let text = "mi moku, li toki e ni: tokipona li pona! 🐱";
+    // Splitting the text at any character boundary gives the same bytes.
+    for (split, _) in text.char_indices() {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.write_text(&text[..split]).unwrap();
+        encoder.write_text(&text[split..]).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(encoded, encode(text), "split at byte {}", split);
+    }
+
+    // Words in separate chunks are attached unless a space is written between them.
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.write_text("toki").unwrap();
+    encoder.write_text("pona").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(decode(&encoded), "tokipona");
+}
+
+#[test]
+fn word_histogram_counts_words() {
+    use toki_pona_encoding::{dict_set::DICT_SET, encoding::word_histogram};
+
+    let histogram = word_histogram(&encode("mi moku. mi pona, tokipona mi 🐱")).unwrap();
+    let count = |word| histogram[&DICT_SET.get_identifier(word).unwrap()];
+    assert_eq!(count("mi"), 3);
+    assert_eq!(count("pona"), 2);
+    assert_eq!(count("moku"), 1);
+    assert_eq!(count("toki"), 1);
+    assert_eq!(histogram.len(), 4);
+
+    assert_eq!(
+        word_histogram(&[0x1a, 1]),
+        Err(DecodeError::TruncatedPayload {
+            claimed: 1,
+            remaining: 0
+        })
+    );
+}
+
+#[test]
+fn sentence_case_capitalizes_sentence_starts() {
+    use toki_pona_encoding::encoding::disassemble;
+
+    let encode_sentence_case = |text: &str| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.sentence_case(true);
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+        encoded
+    };
+
+    let text = "Mi moku. Tokipona li pona! jan Sonja li toki, Toki li pona? Sonja li toki.";
+    let encoded = encode_sentence_case(text);
+    assert_eq!(decode(&encoded), text);
+    assert_eq!(
+        &disassemble(&encoded).unwrap()[..10],
+        [
+            "CAPITALIZE",
+            "WORD(mi)",
+            "WORD(moku)",
+            "PUNCTUATION(.)",
+            "CAPITALIZE",
+            "WORD(toki)",
+            "ATTACH",
+            "WORD(pona)",
+            "WORD(li)",
+            "WORD(pona)",
+        ]
+    );
+    // Capitalised words in the middle of a sentence are proper nouns.
+    let instructions = disassemble(&encoded).unwrap();
+    assert!(instructions.contains(&"RAW_TEXT(\"Toki\")".to_owned()));
+    assert!(instructions.contains(&"RAW_TEXT(\"Sonja\")".to_owned()));
+
+    // Without the option, capitalised words are not words.
+    let mut encoder = Encoder::new(Vec::new());
+    assert!(matches!(
+        encoder.write_text("Mi moku"),
+        Err(EncodeError::UnknownWord(_))
+    ));
+}
+
+#[test]
+fn end_of_stream_separates_messages() {
+    let mut encoded = Vec::new();
+    for text in ["mi moku.", "Toki", "sina pona"] {
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.end_of_stream(true).sentence_case(true);
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+    }
+    // Dropping the encoder writes the marker too.
+    {
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.end_of_stream(true);
+        encoder.write_text("ni li pini").unwrap();
+    }
+    assert_eq!(encoded.iter().filter(|&&b| b == 0x00).count(), 4);
+    assert_eq!(*encoded.last().unwrap(), 0x00);
+
+    let mut reader = &encoded[..];
+    let mut messages = Vec::new();
+    loop {
+        let mut decoded = String::new();
+        let mut decoder = Decoder::new(&mut decoded);
+        let ended = decoder.read_from(&mut reader).unwrap();
+        if !ended {
+            assert!(decoded.is_empty());
+            break;
+        }
+        messages.push(decoded);
+    }
+    assert_eq!(messages, ["mi moku.", "Toki", "sina pona", "ni li pini"]);
+
+    // Without a marker, the decoder reads up to the end of the reader.
+    let mut decoded = String::new();
+    let unframed = encode("mi moku");
+    assert!(!Decoder::new(&mut decoded)
+        .read_from(&mut &unframed[..])
+        .unwrap());
+    assert_eq!(decoded, "mi moku");
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn ruby_annotates_hanzi_with_readings() {
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.sentence_case(true);
+    encoder.write_text("Toki pona, 🐱").unwrap();
+    encoder.finish().unwrap();
+
+    let mut decoded = String::new();
+    Decoder::ruby(&mut decoded).read_bytes(&encoded).unwrap();
+    assert_eq!(
+        decoded,
+        "<ruby>言<rt>Toki</rt></ruby> <ruby>好<rt>pona</rt></ruby>, 🐱"
+    );
+
+    let raw = [0x1a, 3, b'<', b'&', b'>'];
+    let mut decoded = String::new();
+    Decoder::ruby(&mut decoded).read_bytes(&raw).unwrap();
+    assert_eq!(decoded, "&lt;&amp;&gt;");
+}
+
+#[test]
+#[cfg(feature = "variation-tipunsin")]
+fn preserve_case_round_trips_any_casing() {
+    use toki_pona_encoding::variation::Variation;
+
+    use toki_pona_encoding::encoding::disassemble;
+
+    let encode_preserving_case = |text: &str| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.preserve_case(true);
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+        encoded
+    };
+
+    let encoded = encode_preserving_case("tOkI pona");
+    assert_eq!(decode(&encoded), "tOkI pona");
+    assert_eq!(
+        disassemble(&encoded).unwrap(),
+        ["CASE_MASK(01010000)", "WORD(toki)", "WORD(pona)"]
+    );
+
+    // Capitalised words use the cheaper marker.
+    let text = "Toki PONA li KaLa. TokiPona";
+    let encoded = encode_preserving_case(text);
+    assert_eq!(decode(&encoded), text);
+    assert_eq!(encode_preserving_case("Toki").len(), 2);
+    assert_eq!(encode_preserving_case("toki").len(), 1);
+
+    // SetVariation does not change how case is recorded.
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.preserve_case(true);
+    encoder.set_variation(Variation::Tipunsin).unwrap();
+    encoder.write_text("TI pUn").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(decode(&encoded), "TI pUn");
+}
+
+#[test]
+fn assumed_lowercase_rejects_capitals() {
+    let encode_lower = |text: &str| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder
+            .assume_lowercase(true)
+            .sentence_case(true)
+            .raw_text_fallback(true);
+        encoder.write_text(text)?;
+        encoder.finish()?;
+        Ok::<_, EncodeError>(encoded)
+    };
+    assert_eq!(encode_lower("toki pona.").unwrap(), encode("toki pona."));
+    assert!(matches!(
+        encode_lower("Toki pona."),
+        Err(EncodeError::UpperCase(text)) if text == "Toki"
+    ));
+}
+
+#[test]
+fn numbers_convert_to_and_from_words() {
+    use toki_pona_encoding::{
+        encoding::{number_to_words, words_to_number},
+        numerals::{NumeralSystem, MAX_SPELT_NUMBER},
+    };
+
+    for number in 0..=MAX_SPELT_NUMBER {
+        let words = number_to_words(number).unwrap();
+        assert_eq!(words_to_number(&words), Some(number));
+    }
+    assert_eq!(number_to_words(MAX_SPELT_NUMBER + 1), None);
+    let words = ["luka", "tu"].map(|word| DICT_SET.get_identifier(word).unwrap());
+    assert_eq!(number_to_words(7).unwrap(), words);
+    // Words out of order or not as `spell` writes them, or that are not numerals, are not
+    // numbers.
+    assert_eq!(NumeralSystem::NasinNanpaPona.value(&["tu", "luka"]), None);
+    assert_eq!(
+        NumeralSystem::NasinNanpaPona.value(&["tu", "tu", "tu"]),
+        None
+    );
+    assert_eq!(NumeralSystem::NasinNanpaPona.value(&["ali"; 101]), None);
+    assert_eq!(NumeralSystem::NasinNanpaPona.value(&["toki"]), None);
+    assert_eq!(NumeralSystem::Pu.value(&["tu", "wan"]), Some(3));
+    assert_eq!(NumeralSystem::Pu.value(&["mute"]), None);
+}
+
+#[test]
+fn numbers_are_spelt_out_as_words() {
+    use toki_pona_encoding::numerals::NumeralSystem;
+
+    let spell = |text: &str, system| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.numerals(Some(system));
+        encoder.write_text(text)?;
+        encoder.finish()?;
+        Ok::<_, EncodeError>(decode(&encoded))
+    };
+
+    assert_eq!(
+        spell("jan 7 li moku e kili 0.", NumeralSystem::NasinNanpaPona).unwrap(),
+        "jan luka tu li moku e kili ala."
+    );
+    assert_eq!(
+        spell("128", NumeralSystem::NasinNanpaPona).unwrap(),
+        "ali mute luka tu wan"
+    );
+    assert_eq!(spell("jan 3", NumeralSystem::Pu).unwrap(), "jan tu wan");
+    // Digits run together with words are spaced from them.
+    for text in ["jan 3 mute", "jan 3mute"] {
+        assert_eq!(spell(text, NumeralSystem::Pu).unwrap(), "jan tu wan mute");
+    }
+    assert_eq!(
+        spell("mute3mute, 2.", NumeralSystem::Pu).unwrap(),
+        "mute tu wan mute, tu."
+    );
+    assert_eq!(spell("jan 6", NumeralSystem::Pu).unwrap(), "jan mute");
+    assert!(matches!(
+        spell("99999", NumeralSystem::NasinNanpaPona),
+        Err(EncodeError::NumberTooLarge(number)) if number == "99999"
+    ));
+
+    // Without a numeral system, digits are not words.
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.write_text("7").unwrap();
+    assert!(matches!(encoder.finish(), Err(EncodeError::UnknownWord(_))));
+}
+
+#[test]
+fn identifiers_encode_like_text() {
+    use toki_pona_encoding::{dict_set::DICT_SET, encoding::encode_identifiers};
+
+    let words = ["jan", "pona", "li", "toki"]
+        .map(|word| DICT_SET.get_identifier(word).unwrap())
+        .to_vec();
+    let encoded = encode_identifiers(&words).unwrap();
+    assert_eq!(encoded, encode("jan pona li toki"));
+    assert_eq!(decode(&encoded), "jan pona li toki");
+    assert_eq!(encode_identifiers(&[]).unwrap(), []);
+}
+
+#[test]
+fn output_limit_is_the_same_however_bytes_are_read() {
+    use toki_pona_encoding::encoding::UnknownBytes;
+
+    let decode_both = |bytes: &[u8]| {
+        let mut bulk = String::new();
+        let mut decoder = Decoder::new(&mut bulk);
+        decoder.max_output_len(8).unknown_bytes(UnknownBytes::Skip);
+        let bulk_result = decoder.read_bytes(bytes);
+        let mut bytewise = String::new();
+        let mut decoder = Decoder::new(&mut bytewise);
+        decoder.max_output_len(8).unknown_bytes(UnknownBytes::Skip);
+        let bytewise_result = bytes.iter().try_for_each(|byte| decoder.read_byte(*byte));
+        assert_eq!(bulk_result, bytewise_result, "{:x?}", bytes);
+        assert_eq!(bulk, bytewise, "{:x?}", bytes);
+        bulk
+    };
+    // "kama pona" is the text of a single instruction, which does not fit.
+    assert_eq!(decode_both(&[0x03, 0xbc, 0x0c, 0x02]), "");
+    assert_eq!(decode_both(&[0x37, 0xaf, 0x59]), "kalama");
+
+    // A small xorshift generator gives the same streams on every run.
+    let mut seed = 0x2545_f491_u32;
+    for _ in 0..10_000 {
+        let bytes = (0..8)
+            .map(|_| {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                seed as u8
+            })
+            .collect::<Vec<_>>();
+        decode_both(&bytes);
+    }
+}
+
+#[test]
+fn output_limit_is_enforced() {
+    let encoded = encode("toki pona li pona");
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.max_output_len(9);
+    assert_eq!(
+        decoder.read_bytes(&encoded),
+        Err(DecodeError::OutputLimitExceeded { limit: 9 })
+    );
+    assert_eq!(decoded, "toki pona");
+
+    // The limit applies across calls, and when reading byte by byte.
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.max_output_len(12);
+    decoder.read_bytes(&encoded[..2]).unwrap();
+    assert_eq!(
+        encoded[2..]
+            .iter()
+            .try_for_each(|byte| decoder.read_byte(*byte)),
+        Err(DecodeError::OutputLimitExceeded { limit: 12 })
+    );
+    assert!(decoded.len() <= 12);
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.max_output_len(17);
+    assert_eq!(decoder.read_bytes(&encoded), Ok(()));
+}
+
+#[test]
+fn decoding_recovers_after_corruption() {
+    let mut encoded = encode("toki pona li pona tawa mi");
+    // Flip a word byte into a byte that is not an instruction.
+    encoded[1] = 0x05;
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    assert_eq!(
+        decoder.read_bytes(&encoded),
+        Err(DecodeError::UnexpectedByte(0x05))
+    );
+    assert_eq!(decoder.position(), 2);
+    decoder.resync();
+    let position = decoder.position();
+    decoder.read_bytes(&encoded[position..]).unwrap();
+    assert_eq!(decoder.position(), encoded.len());
+    assert_eq!(decoded, "toki li pona tawa mi");
+
+    // Resynchronising skips the remaining bytes of a broken instruction.
+    let corrupt = [0x20, 0x7f, 0x10, encode("pona")[0]];
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    assert_eq!(
+        decoder.read_bytes(&corrupt),
+        Err(DecodeError::UnknownVariation(0x7f))
+    );
+    assert_eq!(decoder.position(), 2);
+    decoder.resync();
+    for byte in &corrupt[2..] {
+        decoder.read_byte(*byte).unwrap();
+    }
+    assert_eq!(decoded, "pona");
+
+    // Bytes of an unfinished instruction, kept until the rest arrive, are counted too.
+    let raw = [Opcode::RawText as u8, 2, b'a', b'b'];
+    let mut decoder = Decoder::new(String::new());
+    decoder.read_bytes(&raw[..3]).unwrap();
+    assert_eq!(decoder.position(), 3);
+    decoder.read_bytes(&raw[3..]).unwrap();
+    assert_eq!(decoder.position(), raw.len());
+}
+
+#[test]
+fn flush_per_word_makes_every_prefix_decodable() {
+    use std::{cell::RefCell, io, rc::Rc};
+
+    // A writer whose bytes can be inspected while the encoder still holds it.
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let shared = Shared::default();
+    let mut encoder = Encoder::new(shared.clone());
+    encoder.flush_per_word(true);
+    let mut decoded_so_far = Vec::new();
+    for chunk in ["mi", " moku", " e", " kili.", " ona", "pona"] {
+        encoder.write_text(chunk).unwrap();
+        decoded_so_far.push(decode(&shared.0.borrow()));
+    }
+    encoder.finish().unwrap();
+    assert_eq!(
+        decoded_so_far,
+        [
+            "mi",
+            "mi moku",
+            "mi moku e",
+            "mi moku e kili.",
+            "mi moku e kili. ona",
+            "mi moku e kili. onapona"
+        ]
+    );
+}
+
+#[test]
+fn explicit_spaces_round_trip() {
+    let encode_explicit = |text: &str| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.explicit_spaces(true).phrases(true);
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+        encoded
+    };
+    for text in [
+        "toki pona",
+        "tenpo ni la mi moku",
+        "mi moku, sina moku.",
+        "tokipona li pona",
+        "ni li \"pona\" :)",
+        "jan (pi toki pona) li pona",
+    ] {
+        assert_eq!(decode(&encode_explicit(text)), text);
+    }
+
+    // Spaces are written instead of attachments.
+    let encoded = encode_explicit("toki pona tokipona");
+    assert_eq!(encoded[0], Opcode::ExplicitSpaces as u8);
+    let count = |opcode: Opcode| encoded.iter().filter(|byte| **byte == opcode as u8).count();
+    assert_eq!(count(Opcode::Space), 2);
+    assert_eq!(count(Opcode::AttachToPrevious), 0);
+}
+
+#[test]
+#[cfg(feature = "variation-sitelen")]
+fn reduplication_marker_replaces_repeated_glyphs() {
+    use toki_pona_encoding::{dict_set::DICT_SET, variation::Variation};
+
+    let glyph = |word| {
+        let word = DICT_SET.get_identifier(word).unwrap();
+        DICT_SET.get_word_variation(word, Variation::SitelenPona)
+    };
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.set_variation(Variation::SitelenPona).unwrap();
+    encoder
+        .write_text("lili lili, mute mute mute lililili")
+        .unwrap();
+    encoder.finish().unwrap();
+
+    let mut decoded = String::new();
+    Decoder::new(&mut decoded)
+        .reduplication_marker(Some("\u{2016}"))
+        .read_bytes(&encoded)
+        .unwrap();
+    let (lili, mute) = (glyph("lili"), glyph("mute"));
+    assert_eq!(
+        decoded,
+        format!("{lili} \u{2016}, {mute} \u{2016} \u{2016} {lili}{lili}")
+    );
+
+    // Without a marker, the glyphs are repeated.
+    assert_eq!(
+        decode(&encoded),
+        format!("{lili} {lili}, {mute} {mute} {mute} {lili}{lili}")
+    );
+}
+
+#[test]
+fn read_n_decodes_a_few_instructions_at_a_time() {
+    let encoded = encode("mi moku, sina moku.");
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    let mut pages = Vec::new();
+    let mut rest = &encoded[..];
+    while !rest.is_empty() {
+        let consumed = decoder.read_n(rest, 4).unwrap();
+        rest = &rest[consumed..];
+        pages.push(consumed);
+    }
+    assert_eq!(decoded, "mi moku, sina moku.");
+    assert_eq!(pages, [4, 2]);
+
+    // An incomplete instruction is kept for the next call.
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    let raw = encode("😀 pona");
+    assert_eq!(decoder.read_n(&raw[..2], 1).unwrap(), 2);
+    assert_eq!(decoder.read_n(&raw[2..], 1).unwrap(), raw.len() - 3);
+    assert_eq!(decoded, "😀");
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn strict_variation_rejects_other_scripts() {
+    use toki_pona_encoding::variation::Variation;
+
+    let encode_hanzi = |text: &str, strict: bool| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.strict_variation(strict);
+        encoder.set_variation(Variation::Hanzi).unwrap();
+        encoder.write_text(text)?;
+        encoder.finish()?;
+        Ok::<_, EncodeError>(encoded)
+    };
+    assert!(encode_hanzi("水 水", true).is_ok());
+    assert!(encode_hanzi("水 telo", false).is_ok());
+    assert!(matches!(
+        encode_hanzi("水 telo", true),
+        Err(EncodeError::UnknownWord(word)) if word == "telo"
+    ));
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn unspaced_hanzi_are_split_into_words() {
+    use toki_pona_encoding::variation::Variation;
+
+    for text in [
+        "我爱你",
+        "大大",
+        "我爱你大山小鱼我爱你大山小鱼我爱你大山小鱼。大大，小鱼！",
+        "我 爱你",
+    ] {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.set_variation(Variation::Hanzi).unwrap();
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(decode(&encoded), text);
+    }
+}
+
+#[test]
+fn pretokenized_words_are_encoded_as_given() {
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.write_text("mi").unwrap();
+    encoder
+        .write_pretokenized(&[("tawa", false), ("ni", true), ("pona", false)])
+        .unwrap();
+    encoder.write_text(" kin").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(decode(&encoded), "mi tawani pona kin");
+    assert_eq!(encoded, encode("mi tawani pona kin"));
+
+    let mut encoder = Encoder::new(Vec::new());
+    assert!(matches!(
+        encoder.write_pretokenized(&[("tawani", false)]),
+        Err(EncodeError::UnknownWord(word)) if word == "tawani"
+    ));
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn variation_hint_describes_the_script() {
+    use toki_pona_encoding::variation::Variation;
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.variation_hint(Variation::Hanzi);
+    encoder.write_text("我爱你").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(
+        encoded[..2],
+        [Opcode::VariationHint as u8, u8::from(Variation::Hanzi)]
+    );
+    assert_eq!(disassemble(&encoded).unwrap()[0], "VARIATION_HINT(tp_ZH)");
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    assert_eq!(decoder.variation_hint(), None);
+    decoder.read_bytes(&encoded).unwrap();
+    assert_eq!(decoder.variation_hint(), Some(Variation::Hanzi));
+    assert_eq!(decoded, "我爱你");
+
+    let mut decoder = Decoder::new(String::new());
+    decoder.read_bytes(&encode("mi olin e sina")).unwrap();
+    assert_eq!(decoder.variation_hint(), None);
+}
+
+#[test]
+fn lengths_longer_than_the_bytes_are_truncated_payloads() {
+    use toki_pona_encoding::encoding::decode as decode_all;
+
+    let truncated = DecodeError::TruncatedPayload {
+        claimed: 255,
+        remaining: 3,
+    };
+    for opcode in [
+        Opcode::RawText,
+        Opcode::PunctuationRun,
+        Opcode::SpeltWord,
+        Opcode::CaseMask,
+    ] {
+        let bytes = [opcode as u8, 255, b'a', b'b', b'c'];
+        assert_eq!(decode_all(&bytes), Err(truncated.clone()));
+        assert_eq!(disassemble(&bytes), Err(truncated.clone()));
+
+        // A streaming decoder waits for the rest of the instruction instead.
+        let mut decoded = String::new();
+        let mut decoder = Decoder::new(&mut decoded);
+        decoder.read_bytes(&bytes).unwrap();
+        assert_eq!(decoder.finish(), Err(truncated.clone()));
+    }
+}
+
+#[test]
+#[cfg(feature = "variation-hanzi")]
+fn same_bytes_decode_in_any_variation() {
+    use toki_pona_encoding::variation::Variation;
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::with_variation(&mut encoded, Variation::Hanzi);
+    encoder.end_of_stream(true);
+    encoder.write_text("我 爱 你").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(encoded[..3], encode("mi olin sina"));
+
+    let decode_in = |variation| {
+        let mut decoded = String::new();
+        Decoder::with_variation(&mut decoded, variation)
+            .read_bytes(&[&encoded[..], &encoded[..]].concat())
+            .unwrap();
+        decoded
+    };
+    assert_eq!(decode_in(Variation::Default), "mi olin sinami olin sina");
+    assert_eq!(decode_in(Variation::Hanzi), "我 爱 你我 爱 你");
+}
+
+#[test]
+fn io_write_reassembles_split_characters() {
+    use std::io::Write;
+
+    let text = "mi olin e sina 🐱 «pona»";
+    let bytes = text.as_bytes();
+    for chunk_len in 1..=4 {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        for chunk in bytes.chunks(chunk_len) {
+            assert_eq!(encoder.write(chunk).unwrap(), chunk.len());
+        }
+        encoder.finish().unwrap();
+        assert_eq!(encoded, encode(text));
+    }
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    std::io::copy(&mut &bytes[..], &mut encoder).unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(decode(&encoded), text);
+
+    // Invalid bytes, and text that ends partway through a character, are errors.
+    let mut encoder = Encoder::new(Vec::new());
+    let err = encoder.write(&[b'm', 0xff]).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.write_all(&"🐱".as_bytes()[..2]).unwrap();
+    assert!(matches!(encoder.finish(), Err(EncodeError::InvalidUtf8)));
+
+    // The text before the partial character is encoded exactly once.
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.write_all(b"toki pona").unwrap();
+    encoder.write_all(&"🐱".as_bytes()[..2]).unwrap();
+    assert!(matches!(encoder.finish(), Err(EncodeError::InvalidUtf8)));
+    assert_eq!(encoded, encode("toki pona"));
+    // So an error in that text is reported, rather than hidden until the encoder is dropped.
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.write_all(b"toki sinpinx").unwrap();
+    encoder.write_all(&"🐱".as_bytes()[..2]).unwrap();
+    assert!(matches!(
+        encoder.finish(),
+        Err(EncodeError::UnknownWord(word)) if word == "sinpinx"
+    ));
+}
+
+#[test]
+fn decode_graphemes_separates_spaced_words() {
+    use toki_pona_encoding::{encoding::decode_graphemes, variation::Variation};
+
+    assert_eq!(
+        decode_graphemes(&encode("mi olin e tokipona. 🐱"), Variation::Default).unwrap(),
+        ["mi", "olin", "e", "tokipona.", "🐱"]
+    );
+    #[cfg(feature = "variation-sitelen")]
+    assert_eq!(
+        decode_graphemes(&encode("toki pona!"), Variation::SitelenPona).unwrap(),
+        ["\u{f196c}", "\u{f1954}!"]
+    );
+    assert_eq!(
+        decode_graphemes(&[0x1a, 3], Variation::Default),
+        Err(DecodeError::TruncatedPayload {
+            claimed: 3,
+            remaining: 0
+        })
+    );
+}
+
+#[test]
+fn code_spans_are_written_verbatim() {
+    let encode_code = |text: &str| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.code_delimiter(Some('`'));
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+        encoded
+    };
+    for text in [
+        "mi kepeken `fn main` lon ilo",
+        "`HashMap` li pona",
+        "nimi `x`pona li pona, `a` `b`.",
+        "mi `Vec<u8>`, ona",
+        "ni li `open taso",
+    ] {
+        assert_eq!(decode(&encode_code(text)), text);
+    }
+    assert_eq!(
+        disassemble(&encode_code("mi kepeken `fn main`")).unwrap(),
+        ["WORD(mi)", "WORD(kepeken)", "CODE_SPAN(\"`fn main`\")"]
+    );
+}
+
+#[test]
+fn decoding_never_starts_with_a_space() {
+    use toki_pona_encoding::{
+        encoding::{encode_instructions, Instruction, UnknownBytes},
+        variation::Variation,
+    };
+
+    let toki = Instruction::TokiPonaWord {
+        word: DICT_SET.get_identifier("toki").unwrap(),
+    };
+    for first in [
+        Instruction::SetVariation {
+            variation: Variation::Default,
+        },
+        Instruction::VariationHint {
+            variation: Variation::Default,
+        },
+        Instruction::Capitalize,
+        Instruction::CaseMask { mask: vec![0] },
+        Instruction::AttachToPrevious,
+        Instruction::ExplicitSpaces,
+        Instruction::EndOfStream,
+    ] {
+        let encoded = encode_instructions(&[first.clone(), toki.clone()]).unwrap();
+        let decoded = decode(&encoded);
+        assert!(
+            decoded.eq_ignore_ascii_case("toki"),
+            "{:?} after {:?}",
+            decoded,
+            first
+        );
+    }
+
+    // Skipping an unknown byte writes nothing, not even a space.
+    let mut decoded = String::new();
+    Decoder::new(&mut decoded)
+        .unknown_bytes(UnknownBytes::Skip)
+        .read_bytes(&[0x01, encode("toki")[0]])
+        .unwrap();
+    assert_eq!(decoded, "toki");
+}
+
+#[test]
+fn names_round_trip_as_cartouches() {
+    let encode_names = |text: &str| {
+        let mut encoded = Vec::new();
+        let mut encoder = Encoder::new(&mut encoded);
+        encoder.sentence_case(true).cartouches(true);
+        encoder.write_text(text).unwrap();
+        encoder.finish().unwrap();
+        encoded
+    };
+    for text in ["mi jan Sonja.", "Sonja li pona", "jan Kentin en jan Lisa"] {
+        let encoded = encode_names(text);
+        assert_eq!(decode(&encoded), text);
+        assert!(disassemble(&encoded)
+            .unwrap()
+            .iter()
+            .any(|instruction| instruction.starts_with("CARTOUCHE(")));
+    }
+    // A name with letters that are not in toki pona is still raw text.
+    assert_eq!(
+        disassemble(&encode_names("jan Bob")).unwrap(),
+        ["WORD(jan)", "RAW_TEXT(\"Bob\")"]
+    );
+    // A cartouche is framed by its own opcodes.
+    let encoded = encode_names("Sonja");
+    assert_eq!(encoded.first(), Some(&(Opcode::CartoucheOpen as u8)));
+    assert_eq!(encoded.last(), Some(&(Opcode::CartoucheClose as u8)));
+    assert_eq!(
+        Decoder::new(&mut String::new()).read_bytes(&[Opcode::CartoucheClose as u8]),
+        Err(DecodeError::UnexpectedByte(Opcode::CartoucheClose as u8))
+    );
+}
+
+#[test]
+fn invalid_cartouches_are_errors() {
+    use toki_pona_encoding::encoding::{
+        encode_instructions, Instruction, UnknownBytes, MAX_CARTOUCHE_WORDS,
+    };
+
+    let toki = encode("toki")[0];
+    let pona = encode("pona")[0];
+    // A byte in a cartouche that is not a word fails the same way whether the bytes are
+    // decoded together or one at a time, even when unknown bytes are skipped.
+    let bytes = [
+        toki,
+        Opcode::CartoucheOpen as u8,
+        pona,
+        Opcode::GroupClose as u8,
+        pona,
+    ];
+    let mut bulk = String::new();
+    let mut decoder = Decoder::new(&mut bulk);
+    decoder.unknown_bytes(UnknownBytes::Skip);
+    assert_eq!(
+        decoder.read_bytes(&bytes),
+        Err(DecodeError::InvalidCartouche)
+    );
+    let bulk_position = decoder.position();
+    let mut bytewise = String::new();
+    let mut decoder = Decoder::new(&mut bytewise);
+    decoder.unknown_bytes(UnknownBytes::Skip);
+    let result = bytes.iter().try_for_each(|byte| decoder.read_byte(*byte));
+    assert_eq!(result, Err(DecodeError::InvalidCartouche));
+    assert_eq!(decoder.position(), bulk_position);
+    assert_eq!(bulk_position, 4);
+    assert_eq!(bulk, bytewise);
+
+    // A cartouche that is never closed stops at the most words a cartouche may hold.
+    let mut unclosed = vec![Opcode::CartoucheOpen as u8];
+    unclosed.extend([pona; MAX_CARTOUCHE_WORDS]);
+    let mut decoder = Decoder::new(String::new());
+    assert_eq!(decoder.read_bytes(&unclosed), Ok(()));
+    assert_eq!(
+        decoder.read_bytes(&[pona]),
+        Err(DecodeError::InvalidCartouche)
+    );
+    let words = vec![DICT_SET.get_identifier("pona").unwrap(); MAX_CARTOUCHE_WORDS + 1];
+    assert!(matches!(
+        encode_instructions(&[Instruction::Cartouche { words }]),
+        Err(EncodeError::CartoucheTooLong { len }) if len == MAX_CARTOUCHE_WORDS + 1
+    ));
+}
+
+#[cfg(feature = "variation-sitelen")]
+#[test]
+fn cartouches_render_in_sitelen_pona() {
+    use toki_pona_encoding::{encoding::transcode, variation::Variation};
+
+    // The glyphs of sona olin nasin jan awen in a cartouche.
+    let name = "\u{f1990}\u{f1961}\u{f1945}\u{f193f}\u{f1911}\u{f1908}\u{f1991}";
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::with_variation(&mut encoded, Variation::SitelenPona);
+    encoder.cartouches(true);
+    encoder.write_text(&format!("\u{f1911} {}", name)).unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(
+        transcode(&encoded, Variation::SitelenPona).unwrap(),
+        format!("\u{f1911} {}", name)
+    );
+    assert_eq!(
+        transcode(&encoded, Variation::Default).unwrap(),
+        "jan Sonja"
+    );
+
+    // A name spelt in letters is written in a cartouche in sitelen pona.
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.sentence_case(true).cartouches(true);
+    encoder.write_text("jan Sonja").unwrap();
+    encoder.finish().unwrap();
+    let sitelen = transcode(&encoded, Variation::SitelenPona).unwrap();
+    assert!(sitelen.starts_with("\u{f1911} \u{f1990}"));
+    assert!(sitelen.ends_with('\u{f1991}'));
+}
+
+#[test]
+fn glyph_overrides_replace_words() {
+    use std::collections::HashMap;
+
+    let toki = DICT_SET.get_identifier("toki").unwrap();
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.end_of_stream(true);
+    encoder.write_text("toki pona").unwrap();
+    encoder.finish().unwrap();
+    encoded.extend(encode("pona toki"));
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.glyph_overrides(HashMap::from([(toki, "\u{1f5e8}")]));
+    decoder.read_bytes(&encoded).unwrap();
+    // The overrides last beyond the end of a message.
+    assert_eq!(decoded, "\u{1f5e8} ponapona \u{1f5e8}");
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::ruby(&mut decoded);
+    decoder.glyph_overrides(HashMap::from([(toki, "<b>&</b>")]));
+    decoder.read_bytes(&encode("toki")).unwrap();
+    assert_eq!(
+        decoded,
+        "<ruby>&lt;b&gt;&amp;&lt;/b&gt;<rt>toki</rt></ruby>"
+    );
+}
+
+#[test]
+fn flushing_continues_the_stream() {
+    use std::{
+        cell::RefCell,
+        io::{self, Write},
+        rc::Rc,
+    };
+
+    /// A writer whose bytes can be read while an encoder writes to it.
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+    impl io::Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let text = "mi moku e kili. sina pona";
+    for split in [2, 7, 15] {
+        let encoded = Rc::new(RefCell::new(Vec::new()));
+        let mut encoder = Encoder::new(Shared(encoded.clone()));
+        encoder.write_text(&text[..split]).unwrap();
+        encoder.flush().unwrap();
+        assert_eq!(decode(&encoded.borrow()), &text[..split]);
+        encoder.write_text(&text[split..]).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(decode(&encoded.borrow()), text);
+        assert_eq!(*encoded.borrow(), encode(text));
+    }
+
+    // Flushing through `io::Write` means the same, so that a buffered writer wrapping the
+    // encoder also encodes the text when it is flushed.
+    let encoded = Rc::new(RefCell::new(Vec::new()));
+    let mut encoder = Encoder::new(Shared(encoded.clone()));
+    let mut writer = io::BufWriter::new(&mut encoder);
+    writer.write_all(&text.as_bytes()[..7]).unwrap();
+    writer.flush().unwrap();
+    assert_eq!(decode(&encoded.borrow()), &text[..7]);
+    writer.write_all(&text.as_bytes()[7..]).unwrap();
+    drop(writer);
+    encoder.finish().unwrap();
+    assert_eq!(*encoded.borrow(), encode(text));
+}
+
+#[test]
+fn word_boundaries_are_marked() {
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.word_boundary(Some("|"));
+    decoder
+        .read_bytes(&encode("tokipona li pona, mi \u{1f44d}"))
+        .unwrap();
+    assert_eq!(decoded, "toki|pona |li |pona, |mi \u{1f44d}");
+}
+
+#[cfg(feature = "variation-hanzi")]
+#[test]
+fn word_boundaries_split_hanzi() {
+    use toki_pona_encoding::variation::Variation;
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::with_variation(&mut encoded, Variation::Hanzi);
+    encoder.write_text("我爱你").unwrap();
+    encoder.finish().unwrap();
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::with_variation(&mut decoded, Variation::Hanzi);
+    decoder.word_boundary(Some("\u{1f}"));
+    decoder.read_bytes(&encoded).unwrap();
+    assert_eq!(decoded, "我\u{1f}爱\u{1f}你");
+}
+
+#[test]
+fn repeated_single_letter_words_round_trip() {
+    for text in ["aaa", "aaaa", "a aaa!", &"a".repeat(300)] {
+        let encoded = encode(text);
+        assert_eq!(decode(&encoded), text);
+        let words = text.matches('a').count();
+        // Each letter is its own word, attached to the one before it.
+        assert_eq!(
+            disassemble(&encoded)
+                .unwrap()
+                .iter()
+                .filter(|instruction| *instruction == "WORD(a)")
+                .count(),
+            words
+        );
+    }
+    assert_eq!(
+        disassemble(&encode("aaa")).unwrap(),
+        ["WORD(a)", "ATTACH", "WORD(a)", "ATTACH", "WORD(a)"]
+    );
+}
+
+#[test]
+fn rich_decoding_describes_each_word() {
+    use toki_pona_encoding::{encoding::decode_rich, variation::Variation};
+
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.sentence_case(true).phrases(true);
+    encoder.write_text("Tokipona, tenpo ni la mi").unwrap();
+    encoder.finish().unwrap();
+
+    let words = decode_rich(&encoded).unwrap();
+    let texts = words
+        .iter()
+        .map(|word| word.text.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(texts, ["Toki", "pona", "tenpo", "ni", "la", "mi"]);
+    let attached = words
+        .iter()
+        .map(|word| word.attached_to_previous)
+        .collect::<Vec<_>>();
+    assert_eq!(attached, [false, true, false, false, false, false]);
+    assert_eq!(words[1].id, DICT_SET.get_identifier("pona").unwrap());
+    assert!(words
+        .iter()
+        .all(|word| word.variation == Variation::Default));
+    let decoded = decode(&encoded);
+    assert_eq!(decoded, "Tokipona, tenpo ni la mi");
+}
+
+#[test]
+fn streams_are_diffed_by_instruction() {
+    use toki_pona_encoding::encoding::{diff, Instruction, InstructionDiff};
+
+    let word = |word| Instruction::TokiPonaWord {
+        word: DICT_SET.get_identifier(word).unwrap(),
+    };
+    let runs = diff(&encode("mi moku e kili"), &encode("mi wile moku e telo")).unwrap();
+    assert_eq!(
+        runs,
+        [
+            InstructionDiff::Equal(vec![word("mi")]),
+            InstructionDiff::Insert(vec![word("wile")]),
+            InstructionDiff::Equal(vec![word("moku"), word("e")]),
+            InstructionDiff::Insert(vec![word("telo")]),
+            InstructionDiff::Delete(vec![word("kili")]),
+        ]
+    );
+    assert_eq!(
+        diff(&encode("toki"), &encode("toki")).unwrap(),
+        [InstructionDiff::Equal(vec![word("toki")])]
+    );
+    assert_eq!(diff(&[], &[]).unwrap(), []);
+    assert_eq!(
+        diff(&[Opcode::RawText as u8], &[]),
+        Err(DecodeError::TruncatedStream)
+    );
+}
+
+#[test]
+fn group_nesting_is_bounded() {
+    let open = Opcode::GroupOpen as u8;
+    let close = Opcode::GroupClose as u8;
+    let toki = encode("toki")[0];
+    let check = |bytes: &[u8]| {
+        let mut decoded = String::new();
+        let mut decoder = Decoder::new(&mut decoded);
+        decoder.max_group_depth(Some(64));
+        decoder.read_bytes(bytes)?;
+        decoder.finish()
+    };
+
+    let mut nested = vec![open; 64];
+    nested.push(toki);
+    nested.extend([close; 64]);
+    assert_eq!(check(&nested), Ok(()));
+
+    let million = vec![open; 1_000_000];
+    assert_eq!(
+        check(&million),
+        Err(DecodeError::NestingTooDeep { limit: 64 })
+    );
+    assert_eq!(check(&[toki, close]), Err(DecodeError::UnmatchedGroup));
+    assert_eq!(check(&[open, toki]), Err(DecodeError::UnmatchedGroup));
+    assert_eq!(
+        check(&[open, toki, Opcode::EndOfStream as u8, toki, close]),
+        Err(DecodeError::UnmatchedGroup)
+    );
+
+    // Without a limit, unbalanced groups decode as they are, without recursion.
+    let mut decoded = String::new();
+    Decoder::new(&mut decoded).read_bytes(&million).unwrap();
+    assert_eq!(decoded.len(), 1_000_000);
+    assert_eq!(decode(&[toki, close]), "toki)");
+}
+
+#[test]
+fn cartouches_count_towards_group_nesting() {
+    let open = Opcode::CartoucheOpen as u8;
+    let close = Opcode::CartoucheClose as u8;
+    let group = Opcode::GroupOpen as u8;
+    let group_close = Opcode::GroupClose as u8;
+    let sona = encode("sona")[0];
+    let check = |bytes: &[u8], limit| {
+        let mut decoded = String::new();
+        let mut decoder = Decoder::new(&mut decoded);
+        decoder.max_group_depth(Some(limit));
+        decoder.read_bytes(bytes)?;
+        decoder.finish()
+    };
+
+    assert_eq!(check(&[group, open, sona, close, group_close], 2), Ok(()));
+    assert_eq!(
+        check(&[group, open, sona, close, group_close], 1),
+        Err(DecodeError::NestingTooDeep { limit: 1 })
+    );
+    // Cartouches cannot nest, so one opened inside another leaves the outer one unclosed.
+    assert_eq!(
+        check(&[open, sona, open, sona, close, close], 4),
+        Err(DecodeError::UnmatchedGroup)
+    );
+    assert_eq!(
+        check(&[open, sona, open, sona, close, close], 1),
+        Err(DecodeError::NestingTooDeep { limit: 1 })
+    );
+    assert_eq!(check(&[open, sona], 4), Err(DecodeError::UnmatchedGroup));
+    assert_eq!(
+        check(&[open, sona, Opcode::EndOfStream as u8], 4),
+        Err(DecodeError::UnmatchedGroup)
+    );
+    let million = vec![open; 1_000_000];
+    assert_eq!(check(&million, 64), Err(DecodeError::UnmatchedGroup));
+    let mut bytewise = Decoder::new(String::new());
+    bytewise.max_group_depth(Some(1));
+    let result = million
+        .iter()
+        .try_for_each(|byte| bytewise.read_byte(*byte));
+    assert_eq!(result, Err(DecodeError::NestingTooDeep { limit: 1 }));
+
+    // Without a limit, these are only invalid cartouches.
+    assert_eq!(
+        Decoder::new(String::new()).read_bytes(&million),
+        Err(DecodeError::InvalidCartouche)
+    );
+}
+
+#[test]
+fn abbreviations_expand_and_contract() {
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.abbreviations(true);
+    encoder.write_text("mi olin e tp, jp mi").unwrap();
+    encoder.finish().unwrap();
+    assert_eq!(
+        disassemble(&encoded).unwrap()[3..5],
+        ["ABBREVIATION(tp)", "PUNCTUATION(,)"]
+    );
+    assert_eq!(decode(&encoded), "mi olin e toki pona, jan pona mi");
+
+    let mut decoded = String::new();
+    let mut decoder = Decoder::new(&mut decoded);
+    decoder.contract_abbreviations(true);
+    decoder.read_bytes(&encoded).unwrap();
+    assert_eq!(decoded, "mi olin e tp, jp mi");
+
+    // Without the option, shorthand is not a word.
+    let mut encoder = Encoder::new(Vec::new());
+    encoder.write_text("tp").unwrap();
+    assert!(matches!(encoder.finish(), Err(EncodeError::UnknownWord(_))));
+    assert_eq!(
+        Decoder::new(&mut String::new()).read_bytes(&[Opcode::Abbreviation as u8, 0xff]),
+        Err(DecodeError::UnknownAbbreviation(0xff))
+    );
+}
+
+#[test]
+fn instructions_expand_to_their_words() {
+    use toki_pona_encoding::encoding::{decode_to_instructions, Instruction};
+
+    let word = |word| DICT_SET.get_identifier(word).unwrap();
+    let mut encoded = Vec::new();
+    let mut encoder = Encoder::new(&mut encoded);
+    encoder.abbreviations(true);
+    encoder.write_text("toki pona li tp.").unwrap();
+    encoder.finish().unwrap();
+    let words = decode_to_instructions(&encoded)
+        .unwrap()
+        .iter()
+        .map(|instruction| instruction.expanded_words(&DICT_SET).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        words,
+        [
+            vec![word("toki")],
+            vec![word("pona")],
+            vec![word("li")],
+            vec![word("toki"), word("pona")],
+            vec![],
+        ]
+    );
+    assert_eq!(
+        Instruction::Phrase { code: 0xff }.expanded_words(&DICT_SET),
+        Err(DecodeError::UnknownPhrase(0xff))
+    );
+}